@@ -20,7 +20,15 @@ pub struct Translator {
 }
 
 impl Translator {
-    pub fn translate_net(from: Net) -> ICombNet {
+    /// The interaction combinator net that behaves as `from`, or `None` if `from` has holes,
+    /// which have no behavior to translate.
+    pub fn translate_net(from: Net) -> Option<ICombNet> {
+        if from.has_holes() {
+            return None;
+        }
+        Some(Self::translate(from))
+    }
+    fn translate(from: Net) -> ICombNet {
         let mut translator = Self::default();
 
         for (a, b) in from.redexes {
@@ -44,7 +52,7 @@ impl Translator {
         translator.net
     }
     fn translate_net_and_merge(&mut self, from: Net) -> Vec<ICombTree> {
-        let mut net = Self::translate_net(from);
+        let mut net = Self::translate(from);
         let mut map = BTreeMap::new();
 
         net.map_vars(&mut |x| {
@@ -248,6 +256,7 @@ impl Translator {
 
                 body
             }
+            Cell::Hole => unreachable!("nets with holes aren't translated"),
         }
     }
 }
@@ -0,0 +1,288 @@
+//! The JSON documents that the commands print with `--json`, for tools that would otherwise
+//! have to read their text. The schema is described in the README, and every stage of the
+//! pipeline has a function here: the parsed book, the compiled nets, their normal forms,
+//! the formulas and the interaction combinator nets.
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::icombs::net as icombs;
+use crate::net::{Net, PartitionOrBox, Tree};
+use crate::syntax::compiler::Compiler;
+use crate::syntax::decompiler::agent_name;
+use crate::syntax::diagnostic::line_column;
+use crate::syntax::module::Loader;
+use crate::syntax::{Argument, AstNet, Book, Diagnostic, Instruction, Span};
+use crate::types::alias::Alias;
+use crate::types::scheme::Scheme;
+use crate::types::show::Notation;
+use crate::types::{Type, TypeError};
+
+/// Fields that a command adds to some of the definitions of a file, by name.
+pub type Stages = BTreeMap<String, Map<String, Value>>;
+
+/// The whole output of a command: a report for each file, and the errors of the command
+/// line itself.
+pub fn document(files: Vec<Value>, errors: Vec<Value>) -> Value {
+    json!({ "files": files, "errors": errors })
+}
+
+/// An error without a place in the source, as in a definition that doesn't exist.
+pub fn message(message: impl Into<String>) -> Value {
+    json!({ "message": message.into(), "span": null })
+}
+
+/// The report of a file that couldn't be read.
+pub fn unreadable(file: &str, error: Value) -> Value {
+    json!({ "file": file, "book": null, "definitions": [], "errors": [error] })
+}
+
+/// Serializes the stages of a file, showing formulas in its notation and with its aliases.
+pub struct Serializer<'a> {
+    pub loader: &'a Loader,
+    pub notation: Notation,
+    pub aliases: &'a [Alias],
+}
+
+impl Serializer<'_> {
+    /// The report of a file: its book, its definitions in order of name, along with the
+    /// fields in `stages`, and its errors.
+    pub fn file(
+        &self,
+        file: &str,
+        book: Option<Value>,
+        compiler: &Compiler,
+        mut stages: Stages,
+        errors: Vec<Value>,
+    ) -> Value {
+        let definitions: Vec<Value> = compiler
+            .global_nets
+            .iter()
+            .map(|(name, definition)| {
+                let (signature, type_error) = match &definition.signature {
+                    Ok(scheme) => (self.scheme(scheme), Value::Null),
+                    Err((e, span)) => (Value::Null, self.type_error(e, Some(span))),
+                };
+                let holes = &definition.holes;
+                let formulas: Vec<Type> = holes.holes.iter().map(|x| x.formula.clone()).collect();
+                let mut scope = Type::named_scope(&formulas, &holes.names);
+                let holes: Vec<Value> = holes
+                    .holes
+                    .iter()
+                    .map(|hole| {
+                        json!({
+                            "name": hole.name,
+                            "formula": hole.formula.show_with(&mut scope, self.notation, self.aliases),
+                            "span": self.span(&hole.span),
+                        })
+                    })
+                    .collect();
+                let mut fields = Map::new();
+                fields.insert("name".to_string(), json!(name));
+                fields.insert("net".to_string(), net(&definition.net));
+                fields.insert("signature".to_string(), signature);
+                fields.insert("type_error".to_string(), type_error);
+                fields.insert("holes".to_string(), json!(holes));
+                fields.append(stages.entry(name.clone()).or_default());
+                Value::Object(fields)
+            })
+            .collect();
+        json!({ "file": file, "book": book, "definitions": definitions, "errors": errors })
+    }
+    pub fn span(&self, span: &Span) -> Value {
+        let (line, column) = line_column(self.loader.source(&span.file), span.start);
+        json!({
+            "file": span.file,
+            "start": span.start,
+            "end": span.end,
+            "line": line + 1,
+            "column": column + 1,
+        })
+    }
+    pub fn diagnostic(&self, diagnostic: &Diagnostic) -> Value {
+        json!({ "message": diagnostic.message, "span": self.span(&diagnostic.span) })
+    }
+    pub fn type_error(&self, error: &TypeError, span: Option<&Span>) -> Value {
+        json!({ "message": error.to_string(), "span": span.map(|x| self.span(x)) })
+    }
+    /// A signature, as in `{"vars": ["a"], "ports": ["a'", "a"]}` for `∀a. |- a', a`.
+    pub fn scheme(&self, scheme: &Scheme) -> Value {
+        let mut scope = Type::named_scope(&scheme.ports, &scheme.names);
+        let ports = self.formulas(&scheme.ports, &mut scope);
+        let vars: Vec<&String> = scheme.vars.iter().map(|x| &scope[x]).collect();
+        json!({ "vars": vars, "ports": ports })
+    }
+    /// The formulas of the ports of a net, as inferred by `types::infer_named`.
+    pub fn types(&self, result: Result<(Vec<Type>, BTreeMap<usize, String>), TypeError>) -> Value {
+        match result {
+            Ok((types, names)) => {
+                let mut scope = Type::named_scope(&types, &names);
+                json!({ "ports": self.formulas(&types, &mut scope), "error": null })
+            }
+            Err(e) => json!({ "ports": null, "error": self.type_error(&e, None) }),
+        }
+    }
+    fn formulas(&self, types: &[Type], scope: &mut BTreeMap<usize, String>) -> Vec<String> {
+        types
+            .iter()
+            .map(|x| x.show_with(scope, self.notation, self.aliases))
+            .collect()
+    }
+    pub fn book(&self, book: &Book) -> Value {
+        let imports: Vec<Value> = book
+            .imports
+            .iter()
+            .map(
+                |x| json!({ "path": x.path, "namespace": x.namespace, "span": self.span(&x.span) }),
+            )
+            .collect();
+        // An alias is shown with the ones declared before it, so that it isn't shown as itself.
+        let aliases: Vec<Value> = book
+            .aliases
+            .iter()
+            .enumerate()
+            .map(|(i, x)| self.alias(x, &book.aliases[..i]))
+            .collect();
+        let definitions: Vec<Value> = book.nets.iter().map(|x| self.ast_net(x)).collect();
+        json!({ "imports": imports, "aliases": aliases, "definitions": definitions })
+    }
+    fn alias(&self, alias: &Alias, before: &[Alias]) -> Value {
+        let mut scope = Type::named_scope(std::slice::from_ref(&alias.body), &alias.names);
+        let formula = alias.body.show_with(&mut scope, self.notation, before);
+        let params: Vec<&String> = alias.params.iter().map(|x| &scope[x]).collect();
+        json!({ "name": alias.name, "params": params, "formula": formula, "span": self.span(&alias.span) })
+    }
+    fn ast_net(&self, net: &AstNet) -> Value {
+        let formulas: Vec<Type> = net
+            .port_types
+            .iter()
+            .flatten()
+            .map(|(x, _)| x.clone())
+            .collect();
+        let mut scope = Type::named_scope(&formulas, &net.type_names);
+        let signature: Vec<Value> = net
+            .port_types
+            .iter()
+            .map(|x| match x {
+                Some((formula, _)) => {
+                    json!(formula.show_with(&mut scope, self.notation, self.aliases))
+                }
+                None => Value::Null,
+            })
+            .collect();
+        let ports: Vec<Value> = net
+            .outputs
+            .iter()
+            .map(|x| self.argument(x, &net.names))
+            .collect();
+        let instructions: Vec<Value> = net
+            .instructions
+            .iter()
+            .map(|x| self.instruction(x, &net.names))
+            .collect();
+        json!({
+            "name": net.name,
+            "ports": ports,
+            "signature": signature,
+            "instructions": instructions,
+            "span": self.span(&net.span),
+        })
+    }
+    fn instruction(&self, instruction: &Instruction, names: &BTreeMap<usize, String>) -> Value {
+        match instruction {
+            Instruction::Multicut(name, trees, span) => json!({
+                "multicut": name,
+                "trees": self.trees(trees, names),
+                "span": self.span(span),
+            }),
+            Instruction::Monocut(a, b, span) => json!({
+                "cut": [self.tree(a, names), self.tree(b, names)],
+                "span": self.span(span),
+            }),
+        }
+    }
+    fn argument(&self, argument: &Argument, names: &BTreeMap<usize, String>) -> Value {
+        match argument {
+            Argument::Partition(trees, span) => {
+                json!({ "partition": self.trees(trees, names), "span": self.span(span) })
+            }
+            Argument::Box(trees, span) => {
+                json!({ "box": self.trees(trees, names), "span": self.span(span) })
+            }
+        }
+    }
+    fn trees(&self, trees: &[crate::syntax::Tree], names: &BTreeMap<usize, String>) -> Vec<Value> {
+        trees.iter().map(|x| self.tree(x, names)).collect()
+    }
+    fn tree(&self, tree: &crate::syntax::Tree, names: &BTreeMap<usize, String>) -> Value {
+        use crate::syntax::Tree::*;
+        match tree {
+            Agent(name, arguments, span) => {
+                let arguments: Vec<Value> =
+                    arguments.iter().map(|x| self.argument(x, names)).collect();
+                json!({ "agent": name, "arguments": arguments, "span": self.span(span) })
+            }
+            Var(id, span) => json!({ "var": id, "name": names.get(id), "span": self.span(span) }),
+        }
+    }
+}
+
+/// A net as it's stored: its free ports, its redexes and its vars, with boxes as nets.
+pub fn net(net: &Net) -> Value {
+    let ports: Vec<Value> = net.ports.iter().map(tree).collect();
+    let redexes: Vec<Value> = net
+        .redexes
+        .iter()
+        .map(|(a, b)| json!([tree(a), tree(b)]))
+        .collect();
+    let vars: Vec<Value> = net
+        .vars
+        .iter()
+        .map(|(id, link)| json!({ "id": id, "name": net.names.get(id), "link": link.as_ref().map(tree) }))
+        .collect();
+    json!({ "ports": ports, "redexes": redexes, "vars": vars })
+}
+
+fn tree(x: &Tree) -> Value {
+    match x {
+        Tree::Var(id) => json!({ "var": id }),
+        Tree::Agent(symbol, arguments) => {
+            let arguments: Vec<Value> = arguments
+                .iter()
+                .map(|x| match x {
+                    PartitionOrBox::Partition(trees) => {
+                        json!({ "partition": trees.iter().map(tree).collect::<Vec<_>>() })
+                    }
+                    PartitionOrBox::Box(boxed) => json!({ "box": net(boxed) }),
+                })
+                .collect();
+            json!({ "agent": agent_name(symbol), "arguments": arguments })
+        }
+    }
+}
+
+/// An interaction combinator net, whose agents are `Con`, `Dup` and `Era`.
+pub fn icombs(net: &icombs::Net) -> Value {
+    let ports: Vec<Value> = net.ports.iter().map(icombs_tree).collect();
+    let redexes: Vec<Value> = net
+        .redexes
+        .iter()
+        .map(|(a, b)| json!([icombs_tree(a), icombs_tree(b)]))
+        .collect();
+    let vars: Vec<Value> = net
+        .vars
+        .iter()
+        .map(|(id, link)| json!({ "id": id, "link": link.as_ref().map(icombs_tree) }))
+        .collect();
+    json!({ "ports": ports, "redexes": redexes, "vars": vars })
+}
+
+fn icombs_tree(tree: &icombs::Tree) -> Value {
+    use icombs::Tree::*;
+    match tree {
+        Var(id) => json!({ "var": id }),
+        Con(a, b) => json!({ "agent": "Con", "ports": [icombs_tree(a), icombs_tree(b)] }),
+        Dup(a, b) => json!({ "agent": "Dup", "ports": [icombs_tree(a), icombs_tree(b)] }),
+        Era => json!({ "agent": "Era", "ports": [] }),
+    }
+}
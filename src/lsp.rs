@@ -0,0 +1,411 @@
+//! A language server for `.line` files, which speaks LSP over the standard input and output.
+//!
+//! Documents are parsed and compiled again whenever they change, and the compiled definitions
+//! answer the requests: diagnostics come from the parser and the compiler, hovers from the
+//! formulas of the wires and go-to-definition from the multicuts.
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::syntax::compiler::{Compiler, AGENTS};
+use crate::syntax::module::Loader;
+use crate::syntax::{Diagnostic, Span};
+use crate::types::show::Notation;
+use crate::types::Type;
+
+// Severities and kinds from the LSP specification.
+const ERROR: u32 = 1;
+const INFORMATION: u32 = 3;
+const FUNCTION: u32 = 3;
+const CONSTRUCTOR: u32 = 4;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Serves the messages of `input` until the client asks to exit or the input ends.
+pub fn run(input: &mut impl BufRead, output: &mut impl Write) -> std::io::Result<()> {
+    let mut server = Server::default();
+    while let Some(message) = read_message(input)? {
+        for reply in server.handle(&message) {
+            write_message(output, &reply)?;
+        }
+        if server.exit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a message along with its `Content-Length` header, or `None` at the end of the input.
+pub fn read_message(input: &mut impl BufRead) -> std::io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "a message has no `Content-Length`",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> std::io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[derive(Default)]
+pub struct Server {
+    documents: BTreeMap<String, Document>,
+    exit: bool,
+}
+
+/// An open document, and what was compiled from its latest text.
+struct Document {
+    text: String,
+    /// The file name of the document in spans.
+    file: String,
+    compiler: Compiler,
+    loader: Loader,
+    diagnostics: Vec<Value>,
+    /// Where each definition is named, including the ones that were imported.
+    definitions: BTreeMap<String, Span>,
+    /// The multicuts of the document, with the span of the name of the definition they use.
+    references: Vec<(String, Span)>,
+}
+
+impl Server {
+    /// Handles a request or a notification, and returns the messages to send back.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    // The whole document is sent on every change.
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": { "name": "linear-nets", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "shutdown" => Value::Null,
+            "exit" => {
+                self.exit = true;
+                return vec![];
+            }
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                return self.update(uri(params), document["text"].as_str().unwrap_or(""));
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                let Some(text) = changes.and_then(|x| x.last()?["text"].as_str()) else {
+                    return vec![];
+                };
+                return self.update(uri(params), text);
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri(params));
+                return vec![publish(uri(params), vec![])];
+            }
+            "textDocument/hover" | "textDocument/definition" | "textDocument/completion" => {
+                let document = self.documents.get(uri(params));
+                let offset = document.and_then(|x| offset(&x.text, &params["position"]));
+                match (document, offset) {
+                    (Some(document), Some(offset)) => match method {
+                        "textDocument/hover" => document.hover(offset),
+                        "textDocument/definition" => document.definition(uri(params), offset),
+                        _ => document.completion(),
+                    },
+                    _ => Value::Null,
+                }
+            }
+            _ if message.get("id").is_some() => {
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("Unknown method `{}`", method),
+                    },
+                })];
+            }
+            // Other notifications, like `initialized`, need no answer.
+            _ => return vec![],
+        };
+        vec![json!({ "jsonrpc": "2.0", "id": message["id"], "result": result })]
+    }
+    /// Compiles the new text of a document, and publishes its diagnostics.
+    fn update(&mut self, uri: &str, text: &str) -> Vec<Value> {
+        let document = Document::new(uri, text.to_string());
+        let diagnostics = publish(uri, document.diagnostics.clone());
+        self.documents.insert(uri.to_string(), document);
+        vec![diagnostics]
+    }
+}
+
+impl Document {
+    fn new(uri: &str, text: String) -> Document {
+        // Imports are relative to the document, then to `LINEAR_NETS_PATH`.
+        let file = uri_to_file(uri);
+        let mut document = Document {
+            text: text.clone(),
+            file: file.clone(),
+            compiler: Compiler::default(),
+            loader: Loader::from_env(),
+            diagnostics: vec![],
+            definitions: BTreeMap::new(),
+            references: vec![],
+        };
+        let book = match document.loader.load_source(&file, text, Path::new("")) {
+            Ok(book) => book,
+            Err(e) => {
+                document.diagnostics.push(document.diagnostic(&e, ERROR));
+                return document;
+            }
+        };
+        for net in &book.nets {
+            document
+                .definitions
+                .insert(net.name().to_string(), net.span().clone());
+            for (name, span) in net.references() {
+                if span.file == file && document.text[span.start..].starts_with(name) {
+                    let span = Span::new(span.start, span.start + name.len(), &file);
+                    document.references.push((name.to_string(), span));
+                }
+            }
+        }
+        let mut diagnostics = vec![];
+        if let Err(errors) = document.compiler.compile_book(book) {
+            diagnostics.extend(errors.iter().map(|e| (e.to_diagnostic(), ERROR)));
+        }
+        // Definitions without a signature still compile when they aren't well-typed.
+        for (name, definition) in &document.compiler.global_nets {
+            if let Some(error) = definition.ill_typed(name) {
+                diagnostics.push((error.to_diagnostic(), ERROR));
+            }
+            let holes = definition.show_holes(name, Notation::Unicode, &document.compiler.aliases);
+            diagnostics.extend(holes.into_iter().map(|x| (x, INFORMATION)));
+        }
+        document.diagnostics = diagnostics
+            .iter()
+            .map(|(x, severity)| document.diagnostic(x, *severity))
+            .collect();
+        document
+    }
+    /// A diagnostic in the format of LSP. Those of other files are shown at the start of
+    /// the document, with the file they come from.
+    fn diagnostic(&self, diagnostic: &Diagnostic, severity: u32) -> Value {
+        let (range, message) = if diagnostic.span.file == self.file {
+            (
+                range(&self.text, &diagnostic.span),
+                diagnostic.message.clone(),
+            )
+        } else {
+            let span = Span::new(0, 0, &self.file);
+            let message = format!("In `{}`: {}", diagnostic.span.file, diagnostic.message);
+            (range(&self.text, &span), message)
+        };
+        json!({
+            "range": range,
+            "severity": severity,
+            "source": "linear-nets",
+            "message": message,
+        })
+    }
+    /// The signature of the definition named at `offset`, or the formula of the wire there.
+    fn hover(&self, offset: usize) -> Value {
+        let aliases = &self.compiler.aliases;
+        let names = self
+            .references
+            .iter()
+            .map(|(name, span)| (name, span))
+            .chain(&self.definitions);
+        for (name, span) in names {
+            if span.file == self.file && span.start <= offset && offset < span.end {
+                let Some(definition) = self.compiler.global_nets.get(name) else {
+                    return Value::Null;
+                };
+                let value = match &definition.signature {
+                    Ok(scheme) => {
+                        format!("{}: {}", name, scheme.show_with(Notation::Unicode, aliases))
+                    }
+                    Err((e, _)) => format!("{}: type error: {}", name, e),
+                };
+                return hover(&self.text, value, span);
+            }
+        }
+        for definition in self.compiler.global_nets.values() {
+            let Some((_, wire)) = definition.wires.at(&self.file, offset) else {
+                continue;
+            };
+            let span = wire
+                .spans
+                .iter()
+                .find(|x| x.file == self.file && x.start <= offset && offset < x.end)
+                .unwrap();
+            let mut scope =
+                Type::named_scope(std::slice::from_ref(&wire.formula), &definition.wires.names);
+            let formula = wire
+                .formula
+                .show_with(&mut scope, Notation::Unicode, aliases);
+            let value = match &wire.name {
+                Some(name) => format!("{}: {}", name, formula),
+                None => formula,
+            };
+            return hover(&self.text, value, span);
+        }
+        Value::Null
+    }
+    /// Where the definition used by the multicut at `offset` is named.
+    fn definition(&self, uri: &str, offset: usize) -> Value {
+        let Some((name, _)) = self
+            .references
+            .iter()
+            .find(|(_, span)| span.start <= offset && offset < span.end)
+        else {
+            return Value::Null;
+        };
+        let Some(span) = self.definitions.get(name) else {
+            return Value::Null;
+        };
+        if span.file == self.file {
+            return json!({ "uri": uri, "range": range(&self.text, span) });
+        }
+        match file_to_uri(&span.file) {
+            Some(uri) => {
+                json!({ "uri": uri, "range": range(self.loader.source(&span.file), span) })
+            }
+            None => Value::Null,
+        }
+    }
+    /// The agents, with the shape of their arguments, and the definitions, with their signatures.
+    fn completion(&self) -> Value {
+        let agents = AGENTS.iter().map(|(name, id)| {
+            json!({
+                "label": name,
+                "kind": CONSTRUCTOR,
+                "detail": crate::syntax::compiler::show_shape(name, &id.args()),
+            })
+        });
+        let definitions = self.compiler.global_nets.iter().map(|(name, definition)| {
+            let detail = match &definition.signature {
+                Ok(scheme) => scheme.show_with(Notation::Unicode, &self.compiler.aliases),
+                Err(_) => "not well-typed".to_string(),
+            };
+            json!({ "label": name, "kind": FUNCTION, "detail": detail })
+        });
+        Value::Array(agents.chain(definitions).collect())
+    }
+}
+
+fn uri(params: &Value) -> &str {
+    params["textDocument"]["uri"].as_str().unwrap_or("")
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn hover(text: &str, value: String, span: &Span) -> Value {
+    json!({
+        "contents": { "kind": "plaintext", "value": value },
+        "range": range(text, span),
+    })
+}
+
+fn range(text: &str, span: &Span) -> Value {
+    json!({ "start": position(text, span.start), "end": position(text, span.end) })
+}
+
+/// The LSP position of a byte offset, whose character counts UTF-16 code units.
+fn position(text: &str, offset: usize) -> Value {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    json!({ "line": before.matches('\n').count(), "character": character })
+}
+
+/// The byte offset of an LSP position.
+fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let mut start = 0;
+    for _ in 0..line {
+        start += text[start..].find('\n')? + 1;
+    }
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+/// The file name of a document in spans, which is its path if it's a file.
+fn uri_to_file(uri: &str) -> String {
+    let Some(path) = uri.strip_prefix("file://") else {
+        return uri.to_string();
+    };
+    let mut bytes = vec![];
+    let mut rest = path.as_bytes();
+    while let [first, tail @ ..] = rest {
+        if *first == b'%'
+            && let Some(hex) = path.get(path.len() - tail.len()..path.len() - tail.len() + 2)
+            && let Ok(byte) = u8::from_str_radix(hex, 16)
+        {
+            bytes.push(byte);
+            rest = &tail[2..];
+        } else {
+            bytes.push(*first);
+            rest = tail;
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn file_to_uri(file: &str) -> Option<String> {
+    if !Path::new(file).is_absolute() {
+        return None;
+    }
+    let mut uri = "file://".to_string();
+    for byte in file.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    Some(uri)
+}
@@ -1,12 +1,15 @@
 #![feature(let_chains)]
 
 use std::io::Read;
+use std::process::ExitCode;
 
 use crate::util::join_with;
-use std::collections::BTreeMap;
 
 pub mod icombs;
+pub mod json;
+pub mod lsp;
 pub mod net;
+pub mod repl;
 pub mod syntax;
 pub mod types;
 pub mod util;
@@ -25,15 +28,11 @@ pub mod test;
 // |
 // V Desugared syntax (list of simple net operations)
 // |
-// | syntax::compiler (using net module)
+// | syntax::compiler (using net module, and types::scheme for signatures)
 // |
 // v Net
 // |
-// | net::rules
-// |
-// v Normalized net
-// |
-// | types::infer
+// | net::rules, then types::infer
 // |
 // v Type of free ports
 // |
@@ -41,73 +40,614 @@ pub mod test;
 // |
 // - Output string
 
-pub fn main() {
-    use syntax::Parser;
-    let mut s = String::new();
-    std::io::stdin().lock().read_to_string(&mut s).unwrap();
-    print!("{}", s);
-    let mut parser = Parser::new(&s);
-    let book = parser.parse_book();
-    let mut compiler = crate::syntax::compiler::Compiler::default();
-    let book = match book {
-        Ok(o) => o,
-        Err(e) => {
-            eprintln!("Syntax error: {}", e);
-            return;
+/// Which definitions a command is about.
+enum Entries {
+    /// `Main` if there is one, every definition otherwise.
+    Default,
+    Name(String),
+    All,
+}
+
+/// Exit status when the program has errors, or isn't formatted for `fmt --check`.
+const FAILURE: u8 = 1;
+/// Exit status when the command line is wrong, or a file can't be read or written.
+const USAGE: u8 = 2;
+
+const HELP: &str = "\
+Usage: linear-nets COMMAND [--ascii] [--json] [OPTIONS] [FILE...]
+
+Commands:
+  check                    parse, compile and type the files, and report what's wrong
+  run [--entry NAME | --all]
+                           print the normal form of `Main`, or of every definition if
+                           there's no `Main`, as a definition
+  types [--cross-check]    print the signature of every definition, and with
+                           `--cross-check` the type of its normal form too
+  emit --target TARGET [--entry NAME]
+                           print the interaction combinator net of `Main`, where TARGET
+                           is `icomb`, `hvm2` or `ivy`
+  fmt [--check]            print the files in the canonical layout in place, or with
+                           `--check` report the ones that aren't
+  prove SEQUENT            search for a definition whose ports have these formulas
+  repl                     start an interactive session
+  lsp                      start a language server on standard input and output
+
+Files are read from standard input when there are none, or for `-`. `--ascii` prints
+formulas in the ASCII notation. With `--json`, `check`, `run`, `types` and `emit` print a
+JSON document on standard output instead, with the errors in it, as described in the README.
+
+The exit status is 0 on success, 1 when the program has errors, or isn't formatted for
+`fmt --check`, and 2 when the command line is wrong or a file can't be read.";
+
+/// How the commands print their results.
+#[derive(Clone, Copy)]
+struct Mode {
+    notation: types::show::Notation,
+    /// Print a JSON document on standard output, errors included, instead of text.
+    json: bool,
+}
+
+/// The formats that `emit` prints interaction combinator nets in.
+enum Target {
+    Icomb,
+    #[cfg(feature = "hvm")]
+    Hvm2,
+    #[cfg(feature = "ivy")]
+    Ivy,
+}
+
+pub fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // Formulas are printed in the ASCII notation of `future-syntax.md` with `--ascii`.
+    let notation = if take_flag(&mut args, "--ascii") {
+        types::show::Notation::Ascii
+    } else {
+        types::show::Notation::Unicode
+    };
+    let json = take_flag(&mut args, "--json");
+    let mode = Mode { notation, json };
+    if args.is_empty() {
+        return usage("A command is missing", json);
+    }
+    let command = args.remove(0);
+    let status = match command.as_str() {
+        "check" => files(args).map(|files| check(&files, mode)),
+        "run" => entries(&mut args).and_then(|entries| Ok(run(&files(args)?, &entries, mode))),
+        "types" => {
+            let cross_check = take_flag(&mut args, "--cross-check");
+            files(args).map(|files| show_signatures(&files, mode, cross_check))
         }
+        "emit" => target(&mut args).and_then(|target| {
+            let entries = entries(&mut args)?;
+            if let Entries::All = entries {
+                return Err("`emit` translates a single definition".to_string());
+            }
+            Ok(emit(&files(args)?, &target, &entries, mode))
+        }),
+        _ if json => Err("`--json` works with `check`, `run`, `types` and `emit`".to_string()),
+        "fmt" => {
+            let check = take_flag(&mut args, "--check");
+            files(args).map(|files| format(&files, check))
+        }
+        "prove" => match &args[..] {
+            [sequent] => Ok(prove(sequent)),
+            _ => Err("`prove` takes a sequent, as in `|- A', A`".to_string()),
+        },
+        "repl" if args.is_empty() => {
+            repl::run(notation);
+            Ok(ExitCode::SUCCESS)
+        }
+        // The language server talks to the editor over stdio.
+        "lsp" if args.is_empty() => {
+            let stdin = std::io::stdin();
+            match lsp::run(&mut stdin.lock(), &mut std::io::stdout().lock()) {
+                Ok(()) => Ok(ExitCode::SUCCESS),
+                Err(e) => {
+                    eprintln!("The language server stopped: {}", e);
+                    Ok(ExitCode::from(FAILURE))
+                }
+            }
+        }
+        "help" | "--help" | "-h" => {
+            println!("{}", HELP);
+            Ok(ExitCode::SUCCESS)
+        }
+        "repl" | "lsp" => Err(format!("`{}` takes no arguments", command)),
+        _ => Err(format!("Unknown command `{}`", command)),
     };
+    status.unwrap_or_else(|e| usage(&e, json))
+}
 
-    println!("----- parse");
-    for net in &book {
-        println!("{}", net);
+/// Reports a mistake in the command line, as a JSON document with `json`.
+fn usage(message: &str, json: bool) -> ExitCode {
+    if json {
+        println!("{}", json::document(vec![], vec![json::message(message)]));
+    } else {
+        eprintln!("{}\n\n{}", message, HELP);
     }
-    println!("----- compile");
-    compiler.compile_book(book);
+    ExitCode::from(USAGE)
+}
 
-    let mut net = compiler.main_net();
+/// Removes `flag` from `args`, and returns whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let found = args.iter().any(|x| x == flag);
+    args.retain(|x| x != flag);
+    found
+}
+
+/// Removes `option` from `args` along with the value after it, and returns the value.
+fn take_value(args: &mut Vec<String>, option: &str) -> Result<Option<String>, String> {
+    let Some(i) = args.iter().position(|x| x == option) else {
+        return Ok(None);
+    };
+    if i + 1 == args.len() {
+        return Err(format!("`{}` needs a value", option));
+    }
+    args.remove(i);
+    Ok(Some(args.remove(i)))
+}
 
-    let mut scope = std::collections::BTreeMap::new();
-    let show_agent = |x| format!("{:?}", x);
-    print!("{}", net.show_net(&show_agent, &mut scope, 0));
+fn entries(args: &mut Vec<String>) -> Result<Entries, String> {
+    let all = take_flag(args, "--all");
+    match (take_value(args, "--entry")?, all) {
+        (Some(_), true) => Err("`--entry` and `--all` can't be used together".to_string()),
+        (Some(name), false) => Ok(Entries::Name(name)),
+        (None, true) => Ok(Entries::All),
+        (None, false) => Ok(Entries::Default),
+    }
+}
 
-    println!("----- reducing...");
-    net.normal(crate::net::rules::apply_rule);
-    println!("----- reduced");
+fn target(args: &mut Vec<String>) -> Result<Target, String> {
+    let target = take_value(args, "--target")?.ok_or("`emit` needs a `--target`")?;
+    match target.as_str() {
+        "icomb" => Ok(Target::Icomb),
+        #[cfg(feature = "hvm")]
+        "hvm2" => Ok(Target::Hvm2),
+        #[cfg(feature = "ivy")]
+        "ivy" => Ok(Target::Ivy),
+        "hvm2" => Err("linear-nets was built without the `hvm` feature".to_string()),
+        "ivy" => Err("linear-nets was built without the `ivy` feature".to_string()),
+        _ => Err(format!(
+            "Unknown target `{}`, it must be `icomb`, `hvm2` or `ivy`",
+            target
+        )),
+    }
+}
 
-    let mut scope = std::collections::BTreeMap::new();
-    let show_agent = |x| format!("{:?}", x);
-    print!("{}", net.show_net(&show_agent, &mut scope, 0));
+/// The files left in `args`, or standard input if there are none.
+fn files(args: Vec<String>) -> Result<Vec<String>, String> {
+    if let Some(option) = args.iter().find(|x| x.starts_with("--")) {
+        return Err(format!("Unknown option `{}`", option));
+    }
+    if args.is_empty() {
+        Ok(vec!["-".to_string()])
+    } else {
+        Ok(args)
+    }
+}
 
-    net.canonical();
+/// The contents of `path`, or of standard input if it's `-`, with the name to use in spans.
+fn read(path: &str) -> Result<(String, String), String> {
+    let file = file_name(path).to_string();
+    let source = if path == "-" {
+        let mut s = String::new();
+        std::io::stdin().lock().read_to_string(&mut s).map(|_| s)
+    } else {
+        std::fs::read_to_string(path)
+    };
+    match source {
+        Ok(source) => Ok((file, source)),
+        Err(e) => Err(format!("Can't read `{}`: {}", file, e)),
+    }
+}
 
-    println!("---- translate to icomb");
-    let mut net_icombs = icombs::Translator::translate_net(net.clone());
-    println!("{}", net_icombs.show());
-    net_icombs.normal();
-    println!("{}", net_icombs.show());
+/// The name of the file at `path` in spans, which is `<stdin>` for `-`.
+fn file_name(path: &str) -> &str {
+    if path == "-" {
+        "<stdin>"
+    } else {
+        path
+    }
+}
 
-    println!("----- infer");
-    let trees = net.substitute_iter(net.ports.iter());
-    let types = types::infer(trees);
-    let mut ctx = BTreeMap::new();
-    println!(
-        "|- {}",
-        join_with(
-            types.into_iter().map(|x| x.show(&mut ctx)),
-            ", ".to_string()
-        )
-    );
+/// A file, loaded along with its imports, and compiled.
+struct Program {
+    /// The name of the file in spans.
+    file: String,
+    loader: syntax::module::Loader,
+    compiler: syntax::compiler::Compiler,
+    mode: Mode,
+    /// The parsed book, with `--json`.
+    book: Option<serde_json::Value>,
+    /// The errors, which are kept for the document with `--json` instead of printed.
+    errors: Vec<serde_json::Value>,
+    /// Whether an error was reported.
+    failed: bool,
+}
 
-    #[cfg(feature = "hvm")]
-    {
-        let hvm_net = icombs::hvm2::EmitHVM2::default().emit_net(net_icombs);
-        println!("---- translate to HVM2");
-        println!("{}", hvm_net.show());
+impl Program {
+    /// Compiles the file at `path`, reporting its errors, or says why it can't be read.
+    fn compile(path: &str, mode: Mode) -> Result<Program, String> {
+        let (file, source) = read(path)?;
+        // Imports are relative to the file, then to `LINEAR_NETS_PATH`.
+        let loader = syntax::module::Loader::from_env();
+        let mut program = Program {
+            file,
+            loader,
+            compiler: syntax::compiler::Compiler::default(),
+            mode,
+            book: None,
+            errors: vec![],
+            failed: false,
+        };
+        let book = program
+            .loader
+            .load_source(&program.file, source, std::path::Path::new(""));
+        let book = match book {
+            Ok(book) => book,
+            Err(e) => {
+                program.report(&e);
+                return Ok(program);
+            }
+        };
+        if mode.json {
+            let serializer = json::Serializer {
+                loader: &program.loader,
+                notation: mode.notation,
+                aliases: &book.aliases,
+            };
+            program.book = Some(serializer.book(&book));
+        }
+        if let Err(errors) = program.compiler.compile_book(book) {
+            for e in errors {
+                program.report(&e.to_diagnostic());
+            }
+        }
+        Ok(program)
     }
-    #[cfg(feature = "ivy")]
-    {
-        let ivy_net = icombs::ivy::EmitIvy::default().emit_net(net_icombs);
-        println!("---- translate to Ivy");
-        println!("{}", ivy_net.show());
+    fn report(&mut self, e: &syntax::Diagnostic) {
+        if self.mode.json {
+            let error = self.serializer().diagnostic(e);
+            self.errors.push(error);
+        } else {
+            eprintln!("{}", e.render(self.loader.source(&e.span.file)));
+        }
+        self.failed = true;
+    }
+    /// Reports an error that isn't about a place in the source.
+    fn report_message(&mut self, message: String) {
+        if self.mode.json {
+            self.errors.push(json::message(message));
+        } else {
+            eprintln!("{}", message);
+        }
+        self.failed = true;
+    }
+    fn serializer(&self) -> json::Serializer<'_> {
+        json::Serializer {
+            loader: &self.loader,
+            notation: self.mode.notation,
+            aliases: &self.compiler.aliases,
+        }
+    }
+    /// The names of the definitions in `entries`, reporting the ones that don't exist.
+    fn entries(&mut self, entries: &Entries) -> Vec<String> {
+        use syntax::compiler::DEFAULT_ENTRY;
+        let names = match entries {
+            Entries::Name(name) => vec![name.clone()],
+            Entries::Default if self.compiler.global_nets.contains_key(DEFAULT_ENTRY) => {
+                vec![DEFAULT_ENTRY.to_string()]
+            }
+            Entries::Default | Entries::All => self.compiler.global_nets.keys().cloned().collect(),
+        };
+        names
+            .into_iter()
+            .filter(|name| {
+                let exists = self.compiler.global_nets.contains_key(name);
+                // Definitions that failed to compile have been reported already.
+                if !exists && !self.compiler.failed.contains(name) {
+                    self.report_message(format!("There is no definition named `{}`", name));
+                }
+                self.failed |= !exists;
+                exists
+            })
+            .collect()
+    }
+    /// The normal form of the definition `name`, with its vars substituted.
+    fn normal(&self, name: &str) -> net::Net {
+        let mut net = self.compiler.global_nets[name].net.clone();
+        net.normal(net::rules::apply_rule);
+        net.canonical();
+        net
+    }
+    /// The `normal` and `types` fields of a definition whose normal form is `net`, for `--json`.
+    fn normal_stages(&self, net: &net::Net) -> serde_json::Map<String, serde_json::Value> {
+        let trees = net.substitute_iter(net.ports.iter());
+        let types = types::infer_named(trees, &net.names);
+        let mut fields = serde_json::Map::new();
+        fields.insert("normal".to_string(), json::net(net));
+        fields.insert("types".to_string(), self.serializer().types(types));
+        fields
+    }
+    fn status(&self) -> u8 {
+        if self.failed {
+            FAILURE
+        } else {
+            0
+        }
+    }
+}
+
+/// Compiles each file and runs `command` on it. With `--json`, the fields that `command`
+/// returns are added to the definitions in the document, which is printed at the end.
+fn each_file(
+    files: &[String],
+    mode: Mode,
+    mut command: impl FnMut(&mut Program) -> json::Stages,
+) -> ExitCode {
+    let mut reports = vec![];
+    let status = worst(files.iter().map(|path| match Program::compile(path, mode) {
+        Ok(mut program) => {
+            let stages = command(&mut program);
+            if mode.json {
+                let errors = std::mem::take(&mut program.errors);
+                let report = program.serializer().file(
+                    &program.file,
+                    program.book.clone(),
+                    &program.compiler,
+                    stages,
+                    errors,
+                );
+                reports.push(report);
+            }
+            program.status()
+        }
+        Err(message) => {
+            if mode.json {
+                reports.push(json::unreadable(file_name(path), json::message(message)));
+            } else {
+                eprintln!("{}", message);
+            }
+            USAGE
+        }
+    }));
+    if mode.json {
+        println!("{}", json::document(reports, vec![]));
+    }
+    status
+}
+
+/// The exit status of a command that ran on several files: the highest of theirs.
+fn worst(statuses: impl Iterator<Item = u8>) -> ExitCode {
+    ExitCode::from(statuses.max().unwrap_or(0))
+}
+
+/// Reports the errors of each file, including the definitions that aren't well-typed,
+/// and the formulas of the holes.
+fn check(files: &[String], mode: Mode) -> ExitCode {
+    each_file(files, mode, |program| {
+        let mut errors = vec![];
+        let mut messages = vec![];
+        let mut ill_typed = false;
+        for (name, definition) in &program.compiler.global_nets {
+            // Definitions without a signature still compile when they aren't well-typed.
+            if let Some(error) = definition.ill_typed(name) {
+                if mode.json {
+                    // The error is the `type_error` of the definition in the document.
+                    ill_typed = true;
+                } else {
+                    errors.push(error.to_diagnostic());
+                }
+            } else if let Err(error) = types::check(&definition.net) {
+                // The signature is inferred as the net is built, so the redexes of the net
+                // should have dual types too.
+                messages.push(format!(
+                    "The redexes of `{}` aren't well-typed: {}",
+                    name, error
+                ));
+            }
+            // The holes are in the document with `--json`.
+            if !mode.json {
+                let aliases = &program.compiler.aliases;
+                for hole in definition.show_holes(name, mode.notation, aliases) {
+                    let source = program.loader.source(&hole.span.file);
+                    eprintln!("{}", hole.render_as("hole", source));
+                }
+            }
+        }
+        for e in errors {
+            program.report(&e);
+        }
+        for message in messages {
+            program.report_message(message);
+        }
+        program.failed |= ill_typed;
+        json::Stages::new()
+    })
+}
+
+/// Prints the normal forms of the entries of each file as definitions.
+fn run(files: &[String], entries: &Entries, mode: Mode) -> ExitCode {
+    each_file(files, mode, |program| {
+        if files.len() > 1 && !mode.json {
+            println!("// {}", program.file);
+        }
+        let mut stages = json::Stages::new();
+        for name in program.entries(entries) {
+            let net = program.normal(&name);
+            let source = syntax::decompiler::decompile(&net, &name);
+            if mode.json {
+                let mut fields = program.normal_stages(&net);
+                fields.insert("source".to_string(), serde_json::json!(source));
+                stages.insert(name.clone(), fields);
+            } else if let Some(source) = &source {
+                print!("{}", source);
+            }
+            if source.is_none() {
+                program.report_message(format!(
+                    "The normal form of `{}` can't be written as a definition",
+                    name
+                ));
+            }
+        }
+        stages
+    })
+}
+
+/// Prints the signature of every definition of each file.
+fn show_signatures(files: &[String], mode: Mode, cross_check: bool) -> ExitCode {
+    each_file(files, mode, |program| {
+        if files.len() > 1 && !mode.json {
+            println!("// {}", program.file);
+        }
+        let mut stages = json::Stages::new();
+        let aliases = &program.compiler.aliases;
+        for (name, definition) in &program.compiler.global_nets {
+            match &definition.signature {
+                Ok(scheme) if !mode.json => {
+                    println!("{}: {}", name, scheme.show_with(mode.notation, aliases))
+                }
+                Ok(_) => {}
+                Err((e, _)) => {
+                    if !mode.json {
+                        println!("{}: type error: {}", name, e);
+                    }
+                    program.failed = true;
+                }
+            }
+            // Typing the normal form gives a type at least as general.
+            if cross_check {
+                let net = program.normal(name);
+                if mode.json {
+                    stages.insert(name.clone(), program.normal_stages(&net));
+                } else {
+                    let trees = net.substitute_iter(net.ports.iter());
+                    let types = types::infer_named(trees, &net.names);
+                    println!(
+                        "  normal form: {}",
+                        show_types(types, mode.notation, aliases)
+                    );
+                }
+            }
+        }
+        stages
+    })
+}
+
+/// Prints the interaction combinator net of the entry of each file, in the format of `target`.
+fn emit(files: &[String], target: &Target, entries: &Entries, mode: Mode) -> ExitCode {
+    let name = match entries {
+        Entries::Name(name) => name.clone(),
+        _ => syntax::compiler::DEFAULT_ENTRY.to_string(),
+    };
+    each_file(files, mode, |program| {
+        let mut stages = json::Stages::new();
+        for name in program.entries(&Entries::Name(name.clone())) {
+            let net = program.normal(&name);
+            let Some(mut net_icombs) = icombs::Translator::translate_net(net.clone()) else {
+                program.report_message(format!(
+                    "`{}` has holes, so it can't be translated to interaction combinators",
+                    name
+                ));
+                continue;
+            };
+            net_icombs.normal();
+            let json_icombs = mode.json.then(|| json::icombs(&net_icombs));
+            let output = match target {
+                Target::Icomb => net_icombs.show(),
+                #[cfg(feature = "hvm")]
+                Target::Hvm2 => icombs::hvm2::EmitHVM2::default()
+                    .emit_net(net_icombs)
+                    .show(),
+                #[cfg(feature = "ivy")]
+                Target::Ivy => icombs::ivy::EmitIvy::default().emit_net(net_icombs).show(),
+            };
+            if let Some(json_icombs) = json_icombs {
+                let mut fields = program.normal_stages(&net);
+                fields.insert("icombs".to_string(), json_icombs);
+                fields.insert("output".to_string(), serde_json::json!(output));
+                stages.insert(name, fields);
+            } else {
+                println!("{}", output);
+            }
+        }
+        stages
+    })
+}
+
+/// Formats each file in place, or only reports the ones that aren't formatted with `check`.
+/// Standard input is printed formatted.
+fn format(files: &[String], check: bool) -> ExitCode {
+    worst(files.iter().map(|path| {
+        let (file, source) = match read(path) {
+            Ok(read) => read,
+            Err(message) => {
+                eprintln!("{}", message);
+                return USAGE;
+            }
+        };
+        let formatted = match syntax::format::format(&source, &file) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}", e.render(&source));
+                return FAILURE;
+            }
+        };
+        if path == "-" && !check {
+            print!("{}", formatted);
+        } else if formatted != source {
+            if check {
+                eprintln!("`{}` isn't formatted", file);
+                return FAILURE;
+            }
+            if let Err(e) = std::fs::write(path, formatted) {
+                eprintln!("Can't write `{}`: {}", file, e);
+                return USAGE;
+            }
+        }
+        0
+    }))
+}
+
+/// Prints a definition that proves `sequent`, as in `|- A', A ⊗ B`.
+fn prove(sequent: &str) -> ExitCode {
+    let sequent = match types::Type::parse_sequent(sequent) {
+        Ok((sequent, _)) => sequent,
+        Err(e) => {
+            eprintln!("{}", e.render(sequent));
+            return ExitCode::from(FAILURE);
+        }
+    };
+    match types::prove::Prover::default().prove("Proof", &sequent) {
+        Some(proof) => {
+            print!("{}", proof.source);
+            ExitCode::SUCCESS
+        }
+        None => {
+            eprintln!("No proof was found");
+            ExitCode::from(FAILURE)
+        }
+    }
+}
+
+fn show_types(
+    result: Result<(Vec<types::Type>, std::collections::BTreeMap<usize, String>), types::TypeError>,
+    notation: types::show::Notation,
+    aliases: &[types::alias::Alias],
+) -> String {
+    match result {
+        Ok((types, names)) => {
+            let mut ctx = types::Type::named_scope(&types, &names);
+            format!(
+                "|- {}",
+                join_with(
+                    types
+                        .into_iter()
+                        .map(|x| x.show_with(&mut ctx, notation, aliases)),
+                    ", ".to_string()
+                )
+            )
+        }
+        Err(e) => format!("type error: {}", e),
     }
 }
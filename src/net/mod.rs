@@ -58,10 +58,13 @@ pub enum SymbolId {
 
     All,
     Any,
+
+    /// A placeholder for a net that hasn't been written yet, as in `_` or `_name`.
+    Hole,
 }
 
 impl SymbolId {
-    fn args(&self) -> Vec<Arg> {
+    pub(crate) fn args(&self) -> Vec<Arg> {
         use SymbolId::*;
         match self {
             Times => vec![Arg::Partition(1), Arg::Partition(1)],
@@ -82,6 +85,8 @@ impl SymbolId {
 
             All => vec![Arg::Partition(1), Arg::Box(3)],
             Any => vec![Arg::Partition(1), Arg::Box(3)],
+
+            Hole => vec![],
         }
     }
 }
@@ -114,6 +119,20 @@ pub enum Tree {
 }
 
 impl Tree {
+    fn is_hole(&self) -> bool {
+        matches!(self, Tree::Agent(SymbolId::Hole, _))
+    }
+    /// Whether a hole appears in the tree, including inside of its boxes.
+    fn has_holes(&self) -> bool {
+        match self {
+            Tree::Var(_) => false,
+            Tree::Agent(SymbolId::Hole, _) => true,
+            Tree::Agent(_, args) => args.iter().any(|x| match x {
+                PartitionOrBox::Partition(trees) => trees.iter().any(Tree::has_holes),
+                PartitionOrBox::Box(net) => net.has_holes(),
+            }),
+        }
+    }
     fn map_vars(&mut self, m: &impl Fn(VarId) -> VarId) {
         use Tree::*;
         match self {
@@ -128,6 +147,8 @@ pub struct Net {
     pub(crate) ports: VecDeque<Tree>,
     pub(crate) redexes: VecDeque<(Tree, Tree)>,
     pub(crate) vars: BTreeMap<usize, Option<Tree>>,
+    /// Names from the source code, for the vars that have one.
+    pub(crate) names: BTreeMap<VarId, String>,
 }
 
 impl Net {
@@ -136,10 +157,16 @@ impl Net {
             ports: vec![].into(),
             redexes: vec![].into(),
             vars: BTreeMap::new(),
+            names: BTreeMap::new(),
         }
     }
-    fn reduce(&mut self, f: fn(&mut Net, Cell, Cell)) -> bool {
+    fn reduce(&mut self, f: fn(&mut Net, Cell, Cell), stuck: &mut VecDeque<(Tree, Tree)>) -> bool {
         if let Some((a, b)) = self.redexes.pop_front() {
+            // A redex with a hole can't be reduced until the hole is filled in.
+            if a.is_hole() || b.is_hole() {
+                stuck.push_back((a, b));
+                return true;
+            }
             f(
                 self,
                 Cell::from_tree(a).unwrap(),
@@ -150,15 +177,25 @@ impl Net {
             false
         }
     }
+    /// Whether a hole appears anywhere in the net, so it isn't finished.
+    pub fn has_holes(&self) -> bool {
+        self.ports.iter().any(Tree::has_holes)
+            || self
+                .redexes
+                .iter()
+                .any(|(a, b)| a.has_holes() || b.has_holes())
+            || self.vars.values().flatten().any(Tree::has_holes)
+    }
     pub fn normal(&mut self, f: fn(&mut Net, Cell, Cell)) {
-        while self.reduce(f) {
-            self.print_net_simple()
-        }
+        let mut stuck = VecDeque::new();
+        while self.reduce(f, &mut stuck) {}
+        self.redexes = stuck;
     }
     pub fn link(&mut self, a: Tree, b: Tree) {
         if let Tree::Var(id) = a {
             match self.vars.remove(&id).unwrap() {
                 Some(a) => {
+                    self.names.remove(&id);
                     self.link(a, b);
                 }
                 None => {
@@ -186,6 +223,8 @@ impl Net {
                 (k, v)
             })
             .collect();
+        let names = core::mem::take(&mut self.names);
+        self.names = names.into_iter().map(|(k, v)| (m(k), v)).collect();
     }
     fn allocate_var_id(&mut self) -> VarId {
         for i in 0.. {
@@ -206,6 +245,15 @@ impl Net {
         net.ports.append(&mut vec![a, b].into());
         net
     }
+    /// A wire that shows up as `name` when the net is printed.
+    pub fn named_wire(name: &str) -> Net {
+        let mut net = Net::wire();
+        let Some(Tree::Var(id)) = net.ports.front() else {
+            unreachable!()
+        };
+        net.names.insert(*id, name.to_string());
+        net
+    }
     pub fn graft(symbol: SymbolId, args: Vec<GraftArg>) -> Net {
         let symbol_fmt = symbol.args();
         assert!(args.len() == args.len());
@@ -248,6 +296,7 @@ impl Net {
         self.ports.append(&mut other.ports);
         self.redexes.append(&mut other.redexes);
         self.vars.append(&mut other.vars);
+        self.names.append(&mut other.names);
         self
     }
     pub fn cut(this: Net, this_port: usize, other: Net, other_port: usize) -> Net {
@@ -324,6 +373,7 @@ impl Net {
                     let Some(Some(mut w)) = self.vars.remove(id) else {
                         unreachable!()
                     };
+                    self.names.remove(id);
                     self.substitute_mut(&mut w);
                     *s = w;
                 } else {
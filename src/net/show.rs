@@ -10,6 +10,7 @@ use std::collections::BTreeMap;
 use crate::net::VarId;
 
 use crate::net::PartitionOrBox;
+use crate::util::pick_given_name;
 use crate::util::pick_name;
 
 impl Net {
@@ -92,6 +93,8 @@ impl Net {
                 {
                     visited.push(*id);
                     self.show_tree(show_agent, scope, visited, indent, b)
+                } else if let Some(name) = self.names.get(id) {
+                    pick_given_name(scope, *id, name)
                 } else {
                     pick_name(scope, *id)
                 }
@@ -119,9 +122,16 @@ impl Net {
                 )
             }
             PartitionOrBox::Box(net) => {
+                // The boxed net has its own vars. The names used outside stay reserved,
+                // so that they don't refer to different wires inside.
+                let mut inner_scope = scope
+                    .values()
+                    .enumerate()
+                    .map(|(i, name)| (VarId::MAX - i, name.clone()))
+                    .collect();
                 format!(
                     "[\n{}{}]",
-                    net.show_net(show_agent, scope, indent + 1),
+                    net.show_net(show_agent, &mut inner_scope, indent + 1),
                     "    ".repeat(indent),
                 )
             }
@@ -30,6 +30,8 @@ pub enum Cell {
     // The last three are boxed together
     All((Tree,), Net),
     Any((Tree,), Net),
+
+    Hole,
 }
 
 impl Cell {
@@ -157,6 +159,10 @@ impl Cell {
                 let [a] = a.try_into().unwrap();
                 Some(Cell::Any((a,), b))
             }
+            SymbolId::Hole => {
+                let []: [PartitionOrBox; 0] = args.try_into().ok()?;
+                Some(Cell::Hole)
+            }
         }
     }
     pub fn from_tree(tree: Tree) -> Option<Cell> {
@@ -185,6 +191,7 @@ impl Cell {
             Cell::Cntr(..) => todo!(),
             Cell::All(..) => todo!(),
             Cell::Any(..) => todo!(),
+            Cell::Hole => Tree::Agent(SymbolId::Hole, vec![]),
         }
     }
 }
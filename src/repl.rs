@@ -0,0 +1,341 @@
+//! An interactive session that keeps a `Compiler` alive between inputs.
+//!
+//! Definitions, aliases and imports are typed as they would be in a file, and compiled as
+//! soon as they are complete. Lines that start with `:` are commands, see `HELP`.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::io::{BufRead, IsTerminal};
+
+use crate::syntax::compiler::{Compiler, Definition};
+use crate::syntax::module::Loader;
+use crate::syntax::{AstNet, Book};
+use crate::types::alias::Alias;
+use crate::types::show::Notation;
+
+const HELP: &str = "\
+Definitions, `type` aliases and imports are compiled once their braces are closed.
+A definition with the name of an existing one replaces it, and the definitions that use it
+are compiled again. A blank line ends an input that isn't complete. Comments between
+inputs are skipped.
+
+Commands, where NAME is the last definition entered if it's left out:
+  :net [NAME]        the net of a definition
+  :normal [NAME]     its normal form
+  :type [NAME]       the type of its normal form
+  :icomb [NAME]      its translation to interaction combinators, and the normal form of that
+  :decompile [NAME]  its normal form, written as a definition that can be entered again
+  :holes [NAME]      the formula each of its holes must have
+  :list              every definition, with its signature
+  :undo              forget the last input
+  :help              this message
+  :quit              end the session";
+
+/// What an input can take back with `:undo`.
+struct Snapshot {
+    global_nets: BTreeMap<String, Definition>,
+    failed: BTreeSet<String>,
+    aliases: Vec<Alias>,
+    definitions: BTreeMap<String, AstNet>,
+    last: Option<String>,
+}
+
+pub struct Repl {
+    compiler: Compiler,
+    loader: Loader,
+    notation: Notation,
+    /// The latest source of each definition, including the ones that failed to compile,
+    /// so that they can be compiled again when a definition they use changes.
+    definitions: BTreeMap<String, AstNet>,
+    /// The state before each input, most recent last.
+    history: Vec<Snapshot>,
+    /// Lines of an input that isn't complete yet.
+    pending: String,
+    /// Number of inputs read so far, which names the file of their spans.
+    inputs: usize,
+    /// The last definition entered, which commands use by default.
+    last: Option<String>,
+    quit: bool,
+}
+
+impl Repl {
+    pub fn new(notation: Notation) -> Self {
+        Repl {
+            compiler: Compiler::default(),
+            // Imports are relative to the working directory, then to `LINEAR_NETS_PATH`.
+            loader: Loader::from_env(),
+            notation,
+            definitions: BTreeMap::new(),
+            history: vec![],
+            pending: String::new(),
+            inputs: 0,
+            last: None,
+            quit: false,
+        }
+    }
+    /// Whether the lines read so far are the start of an input.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+    /// Reads a line, and returns what it prints.
+    pub fn read_line(&mut self, line: &str) -> String {
+        if !self.is_pending() {
+            if let Some(command) = line.trim().strip_prefix(':') {
+                return self.command(command);
+            }
+            if line.trim().is_empty() || line.trim().starts_with("//") {
+                return String::new();
+            }
+        }
+        self.pending.push_str(line);
+        self.pending.push('\n');
+        if is_complete(&self.pending) || line.trim().is_empty() {
+            self.flush()
+        } else {
+            String::new()
+        }
+    }
+    /// Compiles the input that has been read so far, even if it isn't complete.
+    pub fn flush(&mut self) -> String {
+        let source = core::mem::take(&mut self.pending);
+        if source.trim().is_empty() {
+            return String::new();
+        }
+        self.inputs += 1;
+        let file = format!("<repl:{}>", self.inputs);
+        let book = self.loader.load_source_with_aliases(
+            &file,
+            source,
+            std::path::Path::new(""),
+            &self.compiler.aliases,
+        );
+        match book {
+            Ok(book) => self.define(book),
+            Err(e) => format!("{}\n", e.render(self.loader.source(&e.span.file))),
+        }
+    }
+    /// Compiles the definitions of `book`, along with those that use them.
+    fn define(&mut self, book: Book) -> String {
+        self.history.push(Snapshot {
+            global_nets: self.compiler.global_nets.clone(),
+            failed: self.compiler.failed.clone(),
+            aliases: self.compiler.aliases.clone(),
+            definitions: self.definitions.clone(),
+            last: self.last.clone(),
+        });
+        let mut changed = BTreeSet::new();
+        for net in &book.nets {
+            // A duplicate in the same input is reported by the compiler, and the first one is kept.
+            if changed.insert(net.name().to_string()) {
+                self.definitions.insert(net.name().to_string(), net.clone());
+                self.last = Some(net.name().to_string());
+            }
+        }
+        let mut dependents = BTreeSet::new();
+        loop {
+            let more: Vec<String> = self
+                .definitions
+                .iter()
+                .filter(|(name, net)| {
+                    !changed.contains(*name)
+                        && !dependents.contains(*name)
+                        && net
+                            .references()
+                            .any(|(x, _)| changed.contains(x) || dependents.contains(x))
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+            if more.is_empty() {
+                break;
+            }
+            dependents.extend(more);
+        }
+        for name in changed.iter().chain(&dependents) {
+            self.compiler.global_nets.remove(name);
+            self.compiler.failed.remove(name);
+        }
+        let mut nets: Vec<AstNet> = dependents
+            .iter()
+            .map(|name| self.definitions[name].clone())
+            .collect();
+        nets.extend(book.nets);
+        let book = Book {
+            imports: vec![],
+            aliases: book.aliases,
+            nets,
+        };
+        let mut out = String::new();
+        if let Err(errors) = self.compiler.compile_book(book) {
+            for e in errors {
+                let e = e.to_diagnostic();
+                writeln!(out, "{}", e.render(self.loader.source(&e.span.file))).unwrap();
+            }
+        }
+        for name in changed.iter().chain(&dependents) {
+            let Some(definition) = self.compiler.global_nets.get(name) else {
+                continue;
+            };
+            writeln!(out, "{}", self.show_signature(name, definition)).unwrap();
+            out.push_str(&self.show_holes(name, definition));
+        }
+        out
+    }
+    fn command(&mut self, command: &str) -> String {
+        let (command, argument) = match command.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, Some(argument.trim())),
+            None => (command, None),
+        };
+        match command {
+            "net" | "normal" | "type" | "icomb" | "decompile" | "holes" => {
+                let name = match argument.or(self.last.as_deref()) {
+                    Some(name) => name.to_string(),
+                    None => return "There are no definitions yet\n".to_string(),
+                };
+                let Some(definition) = self.compiler.global_nets.get(&name) else {
+                    if self.compiler.failed.contains(&name) {
+                        return format!("`{}` failed to compile\n", name);
+                    }
+                    return format!("There is no definition named `{}`\n", name);
+                };
+                match command {
+                    "net" => show_net(&definition.net),
+                    "normal" => show_net(&normal(definition)),
+                    "type" => self.show_type(definition),
+                    "icomb" => show_icombs(definition).unwrap_or_else(|| {
+                        format!("`{}` has holes, so it can't be translated\n", name)
+                    }),
+                    "decompile" => crate::syntax::decompiler::decompile(&normal(definition), &name)
+                        .unwrap_or_else(|| format!("`{}` can't be decompiled\n", name)),
+                    _ if definition.holes.is_empty() => format!("`{}` has no holes\n", name),
+                    _ => self.show_holes(&name, definition),
+                }
+            }
+            "list" => {
+                let mut out = String::new();
+                for (name, definition) in &self.compiler.global_nets {
+                    writeln!(out, "{}", self.show_signature(name, definition)).unwrap();
+                }
+                for name in &self.compiler.failed {
+                    writeln!(out, "{}: failed to compile", name).unwrap();
+                }
+                out
+            }
+            "undo" => {
+                let Some(snapshot) = self.history.pop() else {
+                    return "There is nothing to undo\n".to_string();
+                };
+                self.compiler.global_nets = snapshot.global_nets;
+                self.compiler.failed = snapshot.failed;
+                self.compiler.aliases = snapshot.aliases;
+                self.definitions = snapshot.definitions;
+                self.last = snapshot.last;
+                "Undid the last input\n".to_string()
+            }
+            "help" => format!("{}\n", HELP),
+            "quit" | "q" => {
+                self.quit = true;
+                String::new()
+            }
+            _ => format!("Unknown command `:{}`, try `:help`\n", command),
+        }
+    }
+    fn show_signature(&self, name: &str, definition: &Definition) -> String {
+        match &definition.signature {
+            Ok(scheme) => format!(
+                "{}: {}",
+                name,
+                scheme.show_with(self.notation, &self.compiler.aliases)
+            ),
+            Err((e, _)) => format!("{}: type error: {}", name, e),
+        }
+    }
+    fn show_type(&self, definition: &Definition) -> String {
+        let mut net = normal(definition);
+        net.canonical();
+        let trees = net.substitute_iter(net.ports.iter());
+        format!(
+            "{}\n",
+            crate::show_types(
+                crate::types::infer_named(trees, &net.names),
+                self.notation,
+                &self.compiler.aliases
+            )
+        )
+    }
+    fn show_holes(&self, name: &str, definition: &Definition) -> String {
+        let mut out = String::new();
+        for hole in definition.show_holes(name, self.notation, &self.compiler.aliases) {
+            writeln!(
+                out,
+                "{}",
+                hole.render_as("hole", self.loader.source(&hole.span.file))
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+/// Whether `source` is a whole input: its braces are balanced, and it has a body unless
+/// it's an alias or an import.
+fn is_complete(source: &str) -> bool {
+    let mut depth = 0;
+    let mut body = false;
+    for line in source.lines() {
+        let line = line.split_once("//").map_or(line, |(code, _)| code);
+        for c in line.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    body = true;
+                }
+                '}' => depth -= 1,
+                _ => (),
+            }
+        }
+    }
+    let source = source.trim_start();
+    depth <= 0 && (body || source.starts_with("type") || source.starts_with("import"))
+}
+
+fn normal(definition: &Definition) -> crate::net::Net {
+    let mut net = definition.net.clone();
+    net.normal(crate::net::rules::apply_rule);
+    net
+}
+
+fn show_net(net: &crate::net::Net) -> String {
+    let mut scope = BTreeMap::new();
+    let show_agent = |x| format!("{:?}", x);
+    net.show_net(&show_agent, &mut scope, 0)
+}
+
+fn show_icombs(definition: &Definition) -> Option<String> {
+    let mut net = normal(definition);
+    net.canonical();
+    let mut net_icombs = crate::icombs::Translator::translate_net(net)?;
+    let translation = net_icombs.show();
+    net_icombs.normal();
+    Some(format!("{}\n{}", translation, net_icombs.show()))
+}
+
+/// Reads lines from the standard input until it ends or `:quit`, printing a prompt when
+/// it's a terminal.
+pub fn run(notation: Notation) {
+    let mut repl = Repl::new(notation);
+    let interactive = std::io::stdin().is_terminal();
+    if interactive {
+        println!("linear-nets repl, type `:help` for the commands");
+    }
+    let mut lines = std::io::stdin().lock().lines();
+    while !repl.quit {
+        if interactive {
+            print!("{} ", if repl.is_pending() { "|" } else { ">" });
+            std::io::Write::flush(&mut std::io::stdout()).unwrap();
+        }
+        let Some(Ok(line)) = lines.next() else {
+            print!("{}", repl.flush());
+            break;
+        };
+        print!("{}", repl.read_line(&line));
+    }
+}
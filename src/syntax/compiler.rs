@@ -1,41 +1,515 @@
 use super::Tree;
+use crate::net::Arg;
 use crate::net::GraftArg;
 use crate::net::Net;
 use crate::net::SymbolId;
+use crate::syntax::AstNet;
+use crate::syntax::Diagnostic;
 use crate::syntax::Instruction;
+use crate::syntax::Span;
+use crate::types::alias::Alias;
+use crate::types::scheme::{Scheme, Typer};
+use crate::types::{Type, TypeError};
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 
+/// The definition that is evaluated when no other one is asked for.
+pub const DEFAULT_ENTRY: &str = "Main";
+
 #[derive(Debug, Default)]
 pub struct Compiler {
     pub wire_to_nets: BTreeMap<usize, (usize, usize)>,
     pub nets: BTreeMap<usize, (Net, Vec<usize>)>,
     pub next_net_id: usize,
-    pub global_nets: BTreeMap<String, Net>,
+    pub global_nets: BTreeMap<String, Definition>,
+    /// Definitions that failed to compile.
+    pub failed: BTreeSet<String>,
+    /// Named formulas of the book, which types are printed with.
+    pub aliases: Vec<Alias>,
+    // The definition and instruction being compiled, for error reporting.
+    definition: String,
+    instruction: String,
+    names: BTreeMap<usize, String>,
+    // Types of the wires of the nets being built, seen from the net they're created in.
+    typer: Typer,
+    wire_types: BTreeMap<usize, Type>,
+    spans: BTreeMap<usize, Vec<Span>>,
+    /// The first type error in the definition, and the instruction that caused it.
+    type_error: Option<(TypeError, Span)>,
+    /// The holes of the definition, with their spans and the var of their principal port.
+    holes: Vec<(String, Span, usize)>,
+    /// The vars from `first_helper` on are made up by the compiler, to build implicit contexts.
+    first_helper: usize,
+    next_var: usize,
+}
+
+/// A compiled definition.
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub net: Net,
+    /// The principal signature of the net, or why it isn't well-typed and the instruction
+    /// where that was found. Multicuts instantiate it instead of typing the callee again.
+    pub signature: Result<Scheme, (TypeError, Span)>,
+    /// The types of the wires of the definition, up to the first type error if there is one.
+    pub wires: WireTypes,
+    /// The holes of the definition, in order of appearance.
+    pub holes: Holes,
+}
+
+/// The holes of a definition.
+#[derive(Debug, Clone, Default)]
+pub struct Holes {
+    pub holes: Vec<HoleType>,
+    /// Names of the propositional variables of the formulas.
+    pub names: BTreeMap<usize, String>,
+}
+
+/// A hole, as in `_` or `_name`, and the formula that the net that fills it in must have.
+#[derive(Debug, Clone)]
+pub struct HoleType {
+    /// The name of the hole, including the `_`.
+    pub name: String,
+    pub formula: Type,
+    pub span: Span,
+}
+
+impl Definition {
+    /// Says which formula each hole must have, pointing at the hole.
+    pub fn show_holes(
+        &self,
+        definition: &str,
+        notation: crate::types::show::Notation,
+        aliases: &[Alias],
+    ) -> Vec<Diagnostic> {
+        self.holes.show(definition, notation, aliases)
+    }
+    /// The error of `definition` if it isn't well-typed. It still compiles, without a signature.
+    pub fn ill_typed(&self, definition: &str) -> Option<CompileError> {
+        let (error, span) = self.signature.as_ref().err()?;
+        Some(CompileError::IllTyped {
+            definition: definition.to_string(),
+            error: Box::new(error.clone()),
+            span: span.clone(),
+        })
+    }
+}
+
+impl Holes {
+    pub fn is_empty(&self) -> bool {
+        self.holes.is_empty()
+    }
+    /// Says which formula each hole of `definition` must have, pointing at the hole.
+    pub fn show(
+        &self,
+        definition: &str,
+        notation: crate::types::show::Notation,
+        aliases: &[Alias],
+    ) -> Vec<Diagnostic> {
+        let formulas: Vec<Type> = self.holes.iter().map(|x| x.formula.clone()).collect();
+        let mut scope = Type::named_scope(&formulas, &self.names);
+        self.holes
+            .iter()
+            .map(|hole| {
+                let message = format!(
+                    "In `{}`, the hole `{}` has type `{}`",
+                    definition,
+                    hole.name,
+                    hole.formula.show_with(&mut scope, notation, aliases)
+                );
+                Diagnostic::new(message, hole.span.clone())
+            })
+            .collect()
+    }
+}
+
+/// The formula of each wire of a definition, including the wires that are cut and the
+/// ones inside of boxes, by var id.
+#[derive(Debug, Clone, Default)]
+pub struct WireTypes {
+    pub wires: BTreeMap<usize, WireType>,
+    /// Names of the propositional variables of the formulas.
+    pub names: BTreeMap<usize, String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WireType {
+    pub name: Option<String>,
+    /// The type of the wire, seen from the net it's a free port of when it's created.
+    /// From the other side, the wire has the dual type.
+    pub formula: Type,
+    /// Where the wire appears in the source, in order.
+    pub spans: Vec<Span>,
+}
+
+impl WireTypes {
+    /// The wire that appears at `offset` of `file`.
+    pub fn at(&self, file: &str, offset: usize) -> Option<(usize, &WireType)> {
+        self.wires
+            .iter()
+            .map(|(id, wire)| (*id, wire))
+            .find(|(_, wire)| {
+                wire.spans
+                    .iter()
+                    .any(|x| x.file == file && x.start <= offset && offset < x.end)
+            })
+    }
+    /// Shows each named wire with its formula, one per line, in order of appearance.
+    pub fn show_in(&self, notation: crate::types::show::Notation) -> String {
+        let mut wires: Vec<&WireType> = self.wires.values().filter(|x| x.name.is_some()).collect();
+        wires.sort_by_key(|x| x.spans.first().map(|x| x.start));
+        let formulas: Vec<Type> = wires.iter().map(|x| x.formula.clone()).collect();
+        let mut scope = Type::named_scope(&formulas, &self.names);
+        crate::util::join_with(
+            wires.iter().map(|x| {
+                format!(
+                    "{}: {}",
+                    x.name.as_ref().unwrap(),
+                    x.formula.show_in(&mut scope, notation)
+                )
+            }),
+            "\n".to_string(),
+        )
+    }
 }
 
+/// The simplicity operation whose condition an instruction broke.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Wire,
+    Graft,
+    Cut,
+    Box,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+    /// `instruction` can't be compiled as a `condition` operation.
+    Simplicity {
+        definition: String,
+        instruction: String,
+        condition: Condition,
+        reason: String,
+        span: Span,
+    },
+    UnknownSymbol {
+        definition: String,
+        symbol: String,
+        span: Span,
+    },
+    UnknownDefinition {
+        definition: String,
+        name: String,
+        span: Span,
+    },
+    /// A multicut refers to a definition that failed to compile.
+    BrokenDependency {
+        definition: String,
+        name: String,
+        span: Span,
+    },
+    /// Definitions that refer to each other through multicuts. `cycle` starts and ends
+    /// with `definition`, and `span` is the multicut that starts the cycle.
+    Cycle {
+        definition: String,
+        cycle: Vec<String>,
+        span: Span,
+    },
+    Duplicate {
+        definition: String,
+        span: Span,
+    },
+    /// The instructions don't produce a single net.
+    Disconnected {
+        definition: String,
+        subnets: usize,
+        span: Span,
+    },
+    /// A port of the definition is not a free port of its net.
+    MissingPort {
+        definition: String,
+        span: Span,
+    },
+    /// The net has free ports which are not listed as ports of the definition.
+    ExtraPorts {
+        definition: String,
+        count: usize,
+        span: Span,
+    },
+    BadPorts {
+        definition: String,
+        span: Span,
+    },
+    /// A net with a signature isn't well-typed. `span` is the instruction that breaks it.
+    IllTyped {
+        definition: String,
+        error: Box<crate::types::TypeError>,
+        span: Span,
+    },
+    /// The net doesn't have the declared type at a port.
+    /// `signature` is the port's annotation, as in `out: A ⊗ B`.
+    TypeMismatch {
+        definition: String,
+        signature: String,
+        inferred: String,
+        span: Span,
+    },
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Condition::Wire => write!(f, "wire"),
+            Condition::Graft => write!(f, "graft"),
+            Condition::Cut => write!(f, "cut"),
+            Condition::Box => write!(f, "box"),
+        }
+    }
+}
+
+impl CompileError {
+    pub fn definition(&self) -> &str {
+        use CompileError::*;
+        match self {
+            Simplicity { definition, .. }
+            | UnknownSymbol { definition, .. }
+            | UnknownDefinition { definition, .. }
+            | BrokenDependency { definition, .. }
+            | Cycle { definition, .. }
+            | Duplicate { definition, .. }
+            | Disconnected { definition, .. }
+            | MissingPort { definition, .. }
+            | ExtraPorts { definition, .. }
+            | BadPorts { definition, .. }
+            | IllTyped { definition, .. }
+            | TypeMismatch { definition, .. } => definition,
+        }
+    }
+    pub fn span(&self) -> &Span {
+        use CompileError::*;
+        match self {
+            Simplicity { span, .. }
+            | UnknownSymbol { span, .. }
+            | UnknownDefinition { span, .. }
+            | BrokenDependency { span, .. }
+            | Cycle { span, .. }
+            | Duplicate { span, .. }
+            | Disconnected { span, .. }
+            | MissingPort { span, .. }
+            | ExtraPorts { span, .. }
+            | BadPorts { span, .. }
+            | IllTyped { span, .. }
+            | TypeMismatch { span, .. } => span,
+        }
+    }
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.to_string(), self.span().clone())
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        use CompileError::*;
+        match self {
+            Simplicity {
+                definition,
+                instruction,
+                condition,
+                reason,
+                ..
+            } => write!(
+                f,
+                "In `{}`, `{}` breaks the {} condition: {}",
+                definition, instruction, condition, reason
+            ),
+            UnknownSymbol {
+                definition, symbol, ..
+            } => write!(f, "In `{}`, unknown symbol `{}`", definition, symbol),
+            UnknownDefinition {
+                definition, name, ..
+            } => write!(f, "In `{}`, unknown definition `{}`", definition, name),
+            BrokenDependency {
+                definition, name, ..
+            } => write!(
+                f,
+                "In `{}`, `{}` can't be used because it failed to compile",
+                definition, name
+            ),
+            Cycle { cycle, .. } if cycle.len() == 2 => {
+                write!(f, "`{}` refers to itself", cycle[0])
+            }
+            Cycle { cycle, .. } => write!(
+                f,
+                "Definitions refer to each other in a cycle: {}",
+                crate::util::join_with(cycle.iter().cloned(), " -> ".to_string())
+            ),
+            Duplicate { definition, .. } => {
+                write!(f, "`{}` is defined more than once", definition)
+            }
+            Disconnected {
+                definition,
+                subnets,
+                ..
+            } => write!(
+                f,
+                "Definition `{}` consists of {} disconnected subnets",
+                definition, subnets
+            ),
+            MissingPort { definition, .. } => write!(
+                f,
+                "In `{}`, this port is not a free port of the definition's net",
+                definition
+            ),
+            ExtraPorts {
+                definition, count, ..
+            } => write!(
+                f,
+                "Definition `{}` leaves {} free ports that are not listed as its ports",
+                definition, count
+            ),
+            BadPorts { definition, .. } => write!(
+                f,
+                "Ports of `{}` must be a single partition, as in `{}(a b c)`",
+                definition, definition
+            ),
+            IllTyped {
+                definition, error, ..
+            } => write!(f, "`{}` isn't well-typed: {}", definition, error),
+            TypeMismatch {
+                definition,
+                signature,
+                inferred,
+                ..
+            } => write!(
+                f,
+                "In `{}`, the port doesn't match its signature `{}`: its type is `{}`",
+                definition, signature, inferred
+            ),
+        }
+    }
+}
+
+/// The agents that can be written in a definition, by name. Holes are written `_` or `_name`.
+pub const AGENTS: [(&str, SymbolId); 15] = [
+    ("Times", SymbolId::Times),
+    ("Par", SymbolId::Par),
+    ("False", SymbolId::False),
+    ("One", SymbolId::One),
+    ("Left", SymbolId::Left),
+    ("Right", SymbolId::Right),
+    ("With", SymbolId::With),
+    ("True", SymbolId::True),
+    ("Exp0", SymbolId::Exp0),
+    ("Exp1", SymbolId::Exp1),
+    ("Weak", SymbolId::Weak),
+    ("Dere", SymbolId::Dere),
+    ("Cntr", SymbolId::Cntr),
+    ("All", SymbolId::All),
+    ("Any", SymbolId::Any),
+];
+
 fn agent_name_to_id(s: &str) -> Option<SymbolId> {
-    match s {
-        "Times" => Some(SymbolId::Times),
-        "Par" => Some(SymbolId::Par),
-        "False" => Some(SymbolId::False),
-        "One" => Some(SymbolId::One),
+    if s.starts_with('_') {
+        return Some(SymbolId::Hole);
+    }
+    AGENTS
+        .iter()
+        .find(|(name, _)| *name == s)
+        .map(|(_, id)| id.clone())
+}
 
-        "Left" => Some(SymbolId::Left),
-        "Right" => Some(SymbolId::Right),
-        "With" => Some(SymbolId::With),
-        "True" => Some(SymbolId::True),
+/// Tarjan's algorithm. A component comes after every component it has edges to.
+fn strongly_connected_components(edges: &[Vec<(usize, Span)>]) -> Vec<Vec<usize>> {
+    struct State<'a> {
+        edges: &'a [Vec<(usize, Span)>],
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        next_index: usize,
+        components: Vec<Vec<usize>>,
+    }
+    fn visit(s: &mut State, node: usize) {
+        s.index[node] = Some(s.next_index);
+        s.low[node] = s.next_index;
+        s.next_index += 1;
+        s.stack.push(node);
+        s.on_stack[node] = true;
+        for (next, _) in s.edges[node].iter() {
+            match s.index[*next] {
+                None => {
+                    visit(s, *next);
+                    s.low[node] = s.low[node].min(s.low[*next]);
+                }
+                Some(index) if s.on_stack[*next] => s.low[node] = s.low[node].min(index),
+                Some(_) => (),
+            }
+        }
+        if Some(s.low[node]) == s.index[node] {
+            let mut component = vec![];
+            while let Some(x) = s.stack.pop() {
+                s.on_stack[x] = false;
+                component.push(x);
+                if x == node {
+                    break;
+                }
+            }
+            component.sort();
+            s.components.push(component);
+        }
+    }
+    let mut s = State {
+        edges,
+        index: vec![None; edges.len()],
+        low: vec![0; edges.len()],
+        stack: vec![],
+        on_stack: vec![false; edges.len()],
+        next_index: 0,
+        components: vec![],
+    };
+    for node in 0..edges.len() {
+        if s.index[node].is_none() {
+            visit(&mut s, node);
+        }
+    }
+    s.components
+}
 
-        "Exp0" => Some(SymbolId::Exp0),
-        "Exp1" => Some(SymbolId::Exp1),
-        "Weak" => Some(SymbolId::Weak),
-        "Dere" => Some(SymbolId::Dere),
-        "Cntr" => Some(SymbolId::Cntr),
+/// A shortest cycle through the first node of `component`, if there is one.
+/// It starts and ends with that node.
+fn find_cycle(component: &[usize], edges: &[Vec<(usize, Span)>]) -> Option<Vec<usize>> {
+    let start = component[0];
+    let mut previous = BTreeMap::new();
+    let mut queue = std::collections::VecDeque::from([start]);
+    while let Some(node) = queue.pop_front() {
+        for (next, _) in edges[node].iter() {
+            if *next == start {
+                let mut cycle = vec![node];
+                while let Some(p) = previous.get(cycle.last().unwrap()) {
+                    cycle.push(*p);
+                }
+                cycle.reverse();
+                cycle.push(start);
+                return Some(cycle);
+            }
+            if component.contains(next) && !previous.contains_key(next) {
+                previous.insert(*next, node);
+                queue.push_back(*next);
+            }
+        }
+    }
+    None
+}
 
-        "All" => Some(SymbolId::All),
-        "Any" => Some(SymbolId::Any),
-        _ => None,
+/// The shape of an agent's arguments, as in `Times(1)(1)` or `With(1)[2][2]`.
+pub(crate) fn show_shape(agent_name: &str, args: &[Arg]) -> String {
+    let mut s = agent_name.to_string();
+    for arg in args {
+        match arg {
+            Arg::Partition(n) => s += &format!("({})", n),
+            Arg::Box(n) => s += &format!("[{}]", n),
+        }
     }
+    s
 }
 
 impl Compiler {
@@ -43,37 +517,154 @@ impl Compiler {
         self.next_net_id += 1;
         self.next_net_id - 1
     }
-    pub fn compile_book(&mut self, book: crate::syntax::Book) {
-        for net in book {
-            self.compile_net(net);
+    fn violation(&self, condition: Condition, reason: &str, span: &Span) -> CompileError {
+        CompileError::Simplicity {
+            definition: self.definition.clone(),
+            instruction: self.instruction.clone(),
+            condition,
+            reason: reason.to_string(),
+            span: span.clone(),
+        }
+    }
+    /// Removes `wire` from the set of free ports, returning its net and address.
+    fn take_wire(
+        &mut self,
+        wire: usize,
+        condition: Condition,
+        span: &Span,
+    ) -> Result<(usize, usize), CompileError> {
+        match self.wire_to_nets.remove(&wire) {
+            Some(x) => Ok(x),
+            None => Err(self.violation(condition, &self.not_a_free_port(wire), span)),
+        }
+    }
+    /// Runs a step of the typing of the definition, unless an earlier step failed.
+    fn type_step(
+        &mut self,
+        span: &Span,
+        step: impl FnOnce(&mut Typer, &mut BTreeMap<usize, Type>) -> Result<(), TypeError>,
+    ) {
+        if self.type_error.is_none()
+            && let Err(e) = step(&mut self.typer, &mut self.wire_types)
+        {
+            self.type_error = Some((e, span.clone()));
+        }
+    }
+    fn not_a_free_port(&self, wire: usize) -> String {
+        match self.names.get(&wire) {
+            Some(name) => format!("`{}` is not a free port of any net", name),
+            None => "wire is not a free port of any net".to_string(),
         }
     }
-    pub fn compile_net(&mut self, net: crate::syntax::AstNet) {
+    /// Compiles every definition in the book, even after one of them fails.
+    /// Definitions are compiled after the ones they refer to, whatever their order in the book.
+    pub fn compile_book(&mut self, book: crate::syntax::Book) -> Result<(), Vec<CompileError>> {
+        let mut errors = vec![];
+        self.aliases.extend(book.aliases);
+        let mut nets: Vec<Option<AstNet>> = vec![];
+        let mut indices = BTreeMap::new();
+        for net in book.nets {
+            if indices.contains_key(&net.name) {
+                self.failed.insert(net.name.clone());
+                errors.push(CompileError::Duplicate {
+                    definition: net.name,
+                    span: net.span,
+                });
+                continue;
+            }
+            indices.insert(net.name.clone(), nets.len());
+            nets.push(Some(net));
+        }
+        let edges: Vec<Vec<(usize, Span)>> = nets
+            .iter()
+            .map(|net| {
+                let net = net.as_ref().unwrap();
+                net.references()
+                    .filter_map(|(name, span)| Some((*indices.get(name)?, span.clone())))
+                    .collect()
+            })
+            .collect();
+        for component in strongly_connected_components(&edges) {
+            if let Some(cycle) = find_cycle(&component, &edges) {
+                let (_, span) = edges[cycle[0]]
+                    .iter()
+                    .find(|(to, _)| *to == cycle[1])
+                    .unwrap();
+                let names: Vec<String> = cycle
+                    .iter()
+                    .map(|i| nets[*i].as_ref().unwrap().name.clone())
+                    .collect();
+                errors.push(CompileError::Cycle {
+                    definition: names[0].clone(),
+                    cycle: names.clone(),
+                    span: span.clone(),
+                });
+                self.failed.extend(names);
+                continue;
+            }
+            let net = nets[component[0]].take().unwrap();
+            let name = net.name.clone();
+            if let Err(e) = self.compile_net(net) {
+                self.failed.insert(name);
+                errors.push(e);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+    pub fn compile_net(&mut self, net: crate::syntax::AstNet) -> Result<(), CompileError> {
         self.wire_to_nets = BTreeMap::new();
         self.nets = BTreeMap::new();
         self.next_net_id = 0;
+        self.definition = net.name.clone();
+        self.names = net.names;
+        self.typer = Typer::default();
+        self.wire_types = BTreeMap::new();
+        self.type_error = None;
+        self.spans = BTreeMap::new();
+        self.holes = vec![];
+        for i in &net.instructions {
+            match i {
+                Instruction::Monocut(a, b, _) => {
+                    self.collect_spans(a);
+                    self.collect_spans(b);
+                }
+                Instruction::Multicut(_, trees, _) => {
+                    trees.iter().for_each(|x| self.collect_spans(x))
+                }
+            }
+        }
+        for i in &net.outputs {
+            let (crate::syntax::Argument::Partition(trees, _)
+            | crate::syntax::Argument::Box(trees, _)) = i;
+            trees.iter().for_each(|x| self.collect_spans(x));
+        }
+        self.first_helper = self
+            .spans
+            .keys()
+            .chain(self.names.keys())
+            .max()
+            .map_or(0, |x| x + 1);
+        self.next_var = self.first_helper;
         for i in net.instructions {
             // println!("{:?}", i);
+            self.instruction = crate::syntax::Named(&i, &self.names).to_string();
             match i {
-                Instruction::Monocut(a, b) => self.compile_monocut(a, b),
-                Instruction::Multicut(a, b) => self.compile_multicut(a, b),
+                Instruction::Monocut(a, b, span) => self.compile_monocut(a, b, span)?,
+                Instruction::Multicut(a, b, span) => self.compile_multicut(a, b, span)?,
             }
             // println!("{:?}\n--", self);
         }
         // If everything was done right, there's exactly one net left.
         if self.nets.len() != 1 {
-            panic!(
-                "Definition consists of more than one disconnected subnets!: \n{}",
-                {
-                    use core::fmt::Write;
-                    let mut s = String::new();
-                    for (net, wires) in self.nets.values() {
-                        writeln!(&mut s, "Net with wires: {:?}", wires).unwrap();
-                        write!(&mut s, "{}", net.show_net_simple()).unwrap();
-                    }
-                    s
-                }
-            )
+            return Err(CompileError::Disconnected {
+                definition: net.name,
+                subnets: self.nets.len(),
+                span: net.span,
+            });
         }
         let (mut new_net, net_wires) = core::mem::take(&mut self.nets)
             .into_iter()
@@ -86,33 +677,588 @@ impl Compiler {
             m.insert(wire, new_net.ports.pop_front().unwrap());
         }
         use crate::syntax::Argument;
-        let Ok([Argument::Partition(wires)]): Result<[Argument; 1], _> = net.outputs.try_into()
+        let Ok([Argument::Partition(wires, _)]): Result<[Argument; 1], _> = net.outputs.try_into()
         else {
-            unreachable!()
+            return Err(CompileError::BadPorts {
+                definition: net.name,
+                span: net.span,
+            });
+        };
+        let mut port_names = vec![];
+        let mut port_types = vec![];
+        let mut port_wires = vec![];
+        for wire in wires {
+            let Tree::Var(wire, span) = wire else {
+                unreachable!()
+            };
+            port_names.push(self.names.get(&wire).cloned().unwrap_or_default());
+            port_types.extend(self.wire_types.get(&wire).cloned());
+            port_wires.push(wire);
+            let Some(port) = m.remove(&wire) else {
+                return Err(CompileError::MissingPort {
+                    definition: net.name,
+                    span,
+                });
+            };
+            new_net.ports.push_back(port);
+        }
+        if !m.is_empty() {
+            return Err(CompileError::ExtraPorts {
+                definition: net.name,
+                count: m.len(),
+                span: net.span,
+            });
+        }
+        let signature = match self.type_error.take() {
+            Some((error, span)) => Err((error, span)),
+            None => Ok(self.typer.generalize(port_types)),
         };
+        if net.port_types.iter().any(Option::is_some) {
+            self.check_signature(&signature, &port_names, &net.port_types, &net.type_names)?;
+        }
+        let holes = self.hole_types(
+            signature.is_ok(),
+            &port_wires,
+            &net.port_types,
+            &net.type_names,
+        );
+        let wires = self.wire_types_of_definition();
+        self.global_nets.insert(
+            net.name,
+            Definition {
+                net: new_net,
+                signature,
+                wires,
+                holes,
+            },
+        );
+        Ok(())
+    }
+    fn collect_spans(&mut self, tree: &Tree) {
+        match tree {
+            Tree::Var(id, span) => self.spans.entry(*id).or_default().push(span.clone()),
+            Tree::Agent(_, args, _) => {
+                for arg in args {
+                    let (crate::syntax::Argument::Partition(trees, _)
+                    | crate::syntax::Argument::Box(trees, _)) = arg;
+                    trees.iter().for_each(|x| self.collect_spans(x));
+                }
+            }
+        }
+    }
+    fn wire_types_of_definition(&mut self) -> WireTypes {
+        let wires = core::mem::take(&mut self.wire_types)
+            .into_iter()
+            .filter(|(id, _)| *id < self.first_helper)
+            .map(|(id, formula)| {
+                let wire = WireType {
+                    name: self.names.get(&id).cloned(),
+                    formula: self.typer.resolve(formula),
+                    spans: self.spans.remove(&id).unwrap_or_default(),
+                };
+                (id, wire)
+            })
+            .collect();
+        WireTypes {
+            wires,
+            names: self.typer.names().clone(),
+        }
+    }
+    /// The formula of each hole, given the rest of the net and the declared types of the ports.
+    /// The declared types are left out when the net isn't `well_typed`, since unifying them
+    /// with a typer that has failed wouldn't tell anything about the holes.
+    fn hole_types(
+        &self,
+        well_typed: bool,
+        port_wires: &[usize],
+        port_types: &crate::syntax::Signature,
+        type_names: &BTreeMap<usize, String>,
+    ) -> Holes {
+        let mut typer = self.typer.clone();
+        // The declared types only add to what is known about the holes, so they're
+        // unified with the ports in a copy of the typer.
+        let declared: Vec<(usize, Type)> = port_wires
+            .iter()
+            .zip(port_types)
+            .filter_map(|(wire, declared)| Some((*wire, declared.as_ref()?.0.clone())))
+            .collect();
+        if well_typed && !self.holes.is_empty() && !declared.is_empty() {
+            let scheme = Scheme {
+                vars: vec![],
+                ports: declared.iter().map(|(_, t)| t.clone()).collect(),
+                names: type_names.clone(),
+            };
+            let types = typer.instantiate(&scheme);
+            for ((wire, _), declared) in declared.iter().zip(types) {
+                // A mismatch is reported by `check_signature`.
+                let _ = typer.cut(self.wire_types[wire].clone(), !declared);
+            }
+        }
+        let holes = self
+            .holes
+            .iter()
+            .map(|(name, span, var)| HoleType {
+                name: name.clone(),
+                formula: self
+                    .wire_types
+                    .get(var)
+                    .map_or(Type::Hole, |x| typer.resolve(x.clone())),
+                span: span.clone(),
+            })
+            .collect();
+        Holes {
+            holes,
+            names: typer.names().clone(),
+        }
+    }
+    /// Checks the declared types of the ports against the signature inferred for the net.
+    fn check_signature(
+        &self,
+        signature: &Result<Scheme, (TypeError, Span)>,
+        port_names: &[String],
+        port_types: &crate::syntax::Signature,
+        type_names: &BTreeMap<usize, String>,
+    ) -> Result<(), CompileError> {
+        let scheme = signature
+            .as_ref()
+            .map_err(|(error, span)| CompileError::IllTyped {
+                definition: self.definition.clone(),
+                error: Box::new(error.clone()),
+                span: span.clone(),
+            })?;
+        use crate::types::show::Notation;
+        let mut subst = BTreeMap::new();
+        for (i, declared) in port_types.iter().enumerate() {
+            let Some((declared, span)) = declared else {
+                continue;
+            };
+            if !crate::types::is_instance(&scheme.ports[i], declared, &mut subst) {
+                let mut scope = Type::named_scope(std::slice::from_ref(declared), type_names);
+                let mut inferred_scope = Type::named_scope(&scheme.ports, &scheme.names);
+                return Err(CompileError::TypeMismatch {
+                    definition: self.definition.clone(),
+                    signature: format!(
+                        "{}: {}",
+                        port_names[i],
+                        declared.show_with(&mut scope, Notation::Unicode, &self.aliases)
+                    ),
+                    inferred: scheme.ports[i].show_with(
+                        &mut inferred_scope,
+                        Notation::Unicode,
+                        &self.aliases,
+                    ),
+                    span: span.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+    /// The compiled net of the definition `name`, to be evaluated.
+    pub fn entry_net(&self, name: &str) -> Option<Net> {
+        self.global_nets.get(name).map(|x| x.net.clone())
+    }
+    /// Simplicity inference: splits a flat list of wires, as in `Times(a b)`, into the
+    /// partitions and boxes that `expected` asks for, grouping wires by the net they're
+    /// a free port of. Each group must match an argument of the same size, and a box
+    /// must contain every free port of its net. Among the valid assignments, the one
+    /// closest to the written order of the groups wins.
+    fn infer_arguments(
+        &self,
+        agent_name: &str,
+        expected: &[Arg],
+        wires: Vec<Tree>,
+        span: &Span,
+    ) -> Result<Vec<super::Argument>, CompileError> {
+        use super::Argument;
+        // Groups of wires that belong to the same net, in order of appearance.
+        let mut groups: Vec<(usize, Vec<Tree>)> = vec![];
         for wire in wires {
-            let Tree::Var(wire) = wire else {
+            let Tree::Var(id, wire_span) = &wire else {
                 unreachable!()
             };
-            new_net.ports.push_back(m.remove(&wire).unwrap());
+            let Some((net_id, _)) = self.wire_to_nets.get(id) else {
+                return Err(self.violation(
+                    Condition::Graft,
+                    &self.not_a_free_port(*id),
+                    wire_span,
+                ));
+            };
+            match groups.iter_mut().find(|(n, _)| n == net_id) {
+                Some((_, group)) => group.push(wire),
+                None => groups.push((*net_id, vec![wire])),
+            }
+        }
+        let fits = |arg: &Arg, (net_id, group): &(usize, Vec<Tree>)| match arg {
+            Arg::Partition(n) => *n == group.len(),
+            Arg::Box(n) => *n == group.len() && self.nets[net_id].1.len() == group.len(),
+        };
+        // Try every assignment of groups to arguments, in lexicographic order.
+        fn assign(
+            expected: &[Arg],
+            groups: &[(usize, Vec<Tree>)],
+            used: &mut Vec<usize>,
+            fits: &impl Fn(&Arg, &(usize, Vec<Tree>)) -> bool,
+        ) -> bool {
+            let Some(arg) = expected.get(used.len()) else {
+                return used.len() == groups.len();
+            };
+            for i in 0..groups.len() {
+                if !used.contains(&i) && fits(arg, &groups[i]) {
+                    used.push(i);
+                    if assign(expected, groups, used, fits) {
+                        return true;
+                    }
+                    used.pop();
+                }
+            }
+            false
+        }
+        let mut used = vec![];
+        if !assign(expected, &groups, &mut used, &fits) {
+            return Err(self.violation(
+                Condition::Graft,
+                &format!(
+                    "the wires can't be split into {} by the nets they belong to",
+                    show_shape(agent_name, expected)
+                ),
+                span,
+            ));
+        }
+        let mut groups: Vec<_> = groups.into_iter().map(|(_, group)| Some(group)).collect();
+        Ok(expected
+            .iter()
+            .zip(used)
+            .map(|(arg, i)| {
+                let group = groups[i].take().unwrap();
+                let span = group[0].span().join(group[group.len() - 1].span());
+                match arg {
+                    Arg::Partition(_) => Argument::Partition(group, span),
+                    Arg::Box(_) => Argument::Box(group, span),
+                }
+            })
+            .collect())
+    }
+    /// `With[a][b]`, `All[x a]`, `Any[x a]` and `Exp1[a]` leave out their context, as the
+    /// `&`, `@`, `$` and `!` operators do. The context is made of the other free ports of the
+    /// boxed nets. They're packed with `Par` inside of the boxes, and the wires they're reached
+    /// through from outside, which keep their vars, are packed with `Times`. Without other
+    /// free ports, the context is empty, and a `False` cell takes it, or `Exp1` is `Exp0`.
+    /// Returns whether `args` were one of these forms, and were compiled.
+    fn compile_implicit_context(
+        &mut self,
+        mut agent_name: &str,
+        args: &[super::Argument],
+        var_id: usize,
+        span: &Span,
+    ) -> Result<bool, CompileError> {
+        use super::Argument;
+        let Some(boxes) = args
+            .iter()
+            .map(|arg| match arg {
+                Argument::Box(trees, _) => trees
+                    .iter()
+                    .map(|x| match x {
+                        Tree::Var(id, _) => Some(*id),
+                        Tree::Agent(..) => None,
+                    })
+                    .collect(),
+                Argument::Partition(..) => None,
+            })
+            .collect::<Option<Vec<Vec<usize>>>>()
+        else {
+            return Ok(false);
+        };
+        let boxes: Vec<&[usize]> = boxes.iter().map(Vec::as_slice).collect();
+        let var = |x: usize| Tree::Var(x, span.clone());
+        let partition = |x: usize| Argument::Partition(vec![var(x)], span.clone());
+        let boxed = |x: Vec<usize>| Argument::Box(x.into_iter().map(var).collect(), span.clone());
+        let mut renames = vec![];
+        let args = match (agent_name, &boxes[..]) {
+            ("With", [left @ [_], right @ [_]]) => {
+                let (Some(left_ports), Some(right_ports)) =
+                    (self.other_ports(left), self.other_ports(right))
+                else {
+                    return Ok(false);
+                };
+                if left_ports.contains(&right[0]) {
+                    return Ok(false);
+                }
+                // The ports of the right box, in the order of the ports of the left box
+                // that have the same name.
+                let mut right_ports: Vec<Option<usize>> =
+                    right_ports.into_iter().map(Some).collect();
+                let mut matched = vec![];
+                for port in &left_ports {
+                    let name = self.names.get(port);
+                    match right_ports
+                        .iter_mut()
+                        .find(|x| x.is_some_and(|x| self.names.get(&x) == name))
+                    {
+                        Some(x) => matched.push(x.take().unwrap()),
+                        None => return Err(self.one_sided(*port, span)),
+                    }
+                }
+                if let Some(port) = right_ports.into_iter().flatten().next() {
+                    return Err(self.one_sided(port, span));
+                }
+                if left_ports.is_empty() {
+                    let mut args = vec![partition(self.graft_helper("One", vec![], span)?)];
+                    for side in [left[0], right[0]] {
+                        let (cell, wires) = self.empty_context(&[side], span)?;
+                        args.push(boxed(vec![wires[0], cell]));
+                    }
+                    args
+                } else {
+                    let (context, left_context) = self.context(&left_ports, &mut renames, span)?;
+                    let right_context = self.pack("Par", &matched, span)?;
+                    vec![
+                        partition(context),
+                        boxed(vec![left[0], left_context]),
+                        boxed(vec![right[0], right_context]),
+                    ]
+                }
+            }
+            ("All" | "Any", [[x, body]]) => {
+                let Some(ports) = self.other_ports(&[*x, *body]) else {
+                    return Ok(false);
+                };
+                if ports.is_empty() {
+                    let context = self.graft_helper("One", vec![], span)?;
+                    let (cell, wires) = self.empty_context(&[*x, *body], span)?;
+                    vec![partition(context), boxed(vec![cell, wires[0], wires[1]])]
+                } else {
+                    let (context, inner) = self.context(&ports, &mut renames, span)?;
+                    vec![partition(context), boxed(vec![inner, *x, *body])]
+                }
+            }
+            ("Exp1", [[value]]) => {
+                let Some(ports) = self.other_ports(&[*value]) else {
+                    return Ok(false);
+                };
+                match ports[..] {
+                    [] => {
+                        agent_name = "Exp0";
+                        vec![boxed(vec![*value])]
+                    }
+                    [port] => {
+                        let (context, inner) = self.context(&[port], &mut renames, span)?;
+                        vec![partition(context), boxed(vec![*value, inner])]
+                    }
+                    _ => {
+                        return Err(self.violation(
+                            Condition::Box,
+                            &format!(
+                                "the boxed net has {} other free ports, but `Exp1` takes one",
+                                ports.len()
+                            ),
+                            span,
+                        ));
+                    }
+                }
+            }
+            _ => return Ok(false),
+        };
+        let agent = Tree::Agent(agent_name.to_string(), args, span.clone());
+        self.compile_monocut(agent, var(var_id), span.clone())?;
+        for (from, to) in renames {
+            self.rename(from, to);
+        }
+        Ok(true)
+    }
+    /// The free ports of the net of `wires` besides `wires`, if they're all from one net.
+    fn other_ports(&self, wires: &[usize]) -> Option<Vec<usize>> {
+        let (net_id, _) = self.wire_to_nets.get(wires.first()?)?;
+        for wire in wires {
+            if self.wire_to_nets.get(wire)?.0 != *net_id {
+                return None;
+            }
+        }
+        Some(
+            self.nets[net_id]
+                .1
+                .iter()
+                .filter(|x| !wires.contains(x))
+                .copied()
+                .collect(),
+        )
+    }
+    fn one_sided(&self, port: usize, span: &Span) -> CompileError {
+        let wire = match self.names.get(&port) {
+            Some(name) => format!("`{}`", name),
+            None => "a wire".to_string(),
+        };
+        self.violation(
+            Condition::Box,
+            &format!(
+                "both boxes must take the same wires from outside, but only one takes {}",
+                wire
+            ),
+            span,
+        )
+    }
+    /// Packs `ports` into the context of a box. Returns the context, outside of the box,
+    /// and the port that packs them, inside. The wires that stand for `ports` outside are
+    /// made under new vars, which are renamed after `ports` once the box is grafted.
+    fn context(
+        &mut self,
+        ports: &[usize],
+        renames: &mut Vec<(usize, usize)>,
+        span: &Span,
+    ) -> Result<(usize, usize), CompileError> {
+        let inner = self.pack("Par", ports, span)?;
+        let mut ends = vec![];
+        for port in ports {
+            let (a, b) = self.helper_wire(self.names.get(port).cloned(), span)?;
+            renames.push((a, *port));
+            ends.push(b);
+        }
+        Ok((self.pack("Times", &ends, span)?, inner))
+    }
+    /// Boxes the net of `ports` in a `False` cell, which stands for an empty context.
+    /// Returns the `False` cell, and the wires that stand for `ports` in the new net.
+    fn empty_context(
+        &mut self,
+        ports: &[usize],
+        span: &Span,
+    ) -> Result<(usize, Vec<usize>), CompileError> {
+        use super::Argument;
+        let inner = self.pack("Par", ports, span)?;
+        let mut ends = vec![];
+        let mut wires = vec![];
+        for _ in ports {
+            let (a, b) = self.helper_wire(None, span)?;
+            ends.push(a);
+            wires.push(b);
+        }
+        let outer = self.pack("Times", &ends, span)?;
+        let args = vec![
+            Argument::Partition(vec![Tree::Var(outer, span.clone())], span.clone()),
+            Argument::Box(vec![Tree::Var(inner, span.clone())], span.clone()),
+        ];
+        Ok((self.graft_helper("False", args, span)?, wires))
+    }
+    /// Packs `wires` into one, with `Par` when they're from the same net, and `Times` when
+    /// they're each from their own.
+    fn pack(&mut self, agent: &str, wires: &[usize], span: &Span) -> Result<usize, CompileError> {
+        use super::Argument;
+        let var = |x: usize| Tree::Var(x, span.clone());
+        match wires {
+            [] => unreachable!(),
+            [wire] => Ok(*wire),
+            [wire, rest @ ..] => {
+                let rest = self.pack(agent, rest, span)?;
+                let args = if agent == "Par" {
+                    vec![Argument::Partition(
+                        vec![var(*wire), var(rest)],
+                        span.clone(),
+                    )]
+                } else {
+                    vec![
+                        Argument::Partition(vec![var(*wire)], span.clone()),
+                        Argument::Partition(vec![var(rest)], span.clone()),
+                    ]
+                };
+                self.graft_helper(agent, args, span)
+            }
+        }
+    }
+    /// Grafts `agent` onto a new var, which is returned.
+    fn graft_helper(
+        &mut self,
+        agent: &str,
+        args: Vec<super::Argument>,
+        span: &Span,
+    ) -> Result<usize, CompileError> {
+        let var = self.helper_var();
+        let agent = Tree::Agent(agent.to_string(), args, span.clone());
+        self.compile_monocut(agent, Tree::Var(var, span.clone()), span.clone())?;
+        Ok(var)
+    }
+    /// A new wire, named `name`, and its two ends.
+    fn helper_wire(
+        &mut self,
+        name: Option<String>,
+        span: &Span,
+    ) -> Result<(usize, usize), CompileError> {
+        let (a, b) = (self.helper_var(), self.helper_var());
+        if let Some(name) = name {
+            self.names.insert(a, name);
         }
-        assert!(m.is_empty(), "Extra wires were left!");
-        self.global_nets.insert(net.name, new_net);
+        self.compile_monocut(
+            Tree::Var(a, span.clone()),
+            Tree::Var(b, span.clone()),
+            span.clone(),
+        )?;
+        self.names.remove(&a);
+        Ok((a, b))
+    }
+    fn helper_var(&mut self) -> usize {
+        self.next_var += 1;
+        self.next_var - 1
     }
-    pub fn main_net(&mut self) -> Net {
-        self.global_nets.get("Main").unwrap().clone()
+    /// Makes the free port `from` go by `to`, which is no longer used.
+    fn rename(&mut self, from: usize, to: usize) {
+        let (net_id, addr) = self.wire_to_nets.remove(&from).unwrap();
+        self.wire_to_nets.insert(to, (net_id, addr));
+        for wire in self.nets.get_mut(&net_id).unwrap().1.iter_mut() {
+            if *wire == from {
+                *wire = to;
+            }
+        }
+        if let Some(formula) = self.wire_types.remove(&from) {
+            self.wire_types.insert(to, formula);
+        }
     }
-    fn compile_multicut(&mut self, name: String, trees: Vec<Tree>) {
-        let mut net = self.global_nets.get(&name).unwrap().clone();
+    fn compile_multicut(
+        &mut self,
+        name: String,
+        trees: Vec<Tree>,
+        span: Span,
+    ) -> Result<(), CompileError> {
+        let Some(definition) = self.global_nets.get(&name) else {
+            if self.failed.contains(&name) {
+                return Err(CompileError::BrokenDependency {
+                    definition: self.definition.clone(),
+                    name,
+                    span,
+                });
+            }
+            return Err(CompileError::UnknownDefinition {
+                definition: self.definition.clone(),
+                name,
+                span,
+            });
+        };
+        let mut net = definition.net.clone();
+        let signature = definition.signature.clone();
+        if net.ports.len() != trees.len() {
+            return Err(self.violation(
+                Condition::Cut,
+                &format!(
+                    "`{}` has {} ports, but {} were given",
+                    name,
+                    net.ports.len(),
+                    trees.len()
+                ),
+                &span,
+            ));
+        }
         let new_net_id = self.make_new_net_id();
         let mut new_vars = vec![];
         let mut new_index = 0;
+        let mut cut_wires = vec![];
         for wire in trees.into_iter() {
-            let Tree::Var(wire) = wire else {
+            let Tree::Var(wire, wire_span) = wire else {
                 unreachable!()
             };
-            let (part_net_id, addr) = self.wire_to_nets.remove(&wire).unwrap();
-            let (part_net, part_wires) = self.nets.remove(&part_net_id).unwrap();
+            let (part_net_id, addr) = self.take_wire(wire, Condition::Cut, &wire_span)?;
+            let Some((part_net, part_wires)) = self.nets.remove(&part_net_id) else {
+                return Err(self.violation(
+                    Condition::Cut,
+                    "this wire belongs to the same net as a previous wire of the multicut",
+                    &wire_span,
+                ));
+            };
             for part_wire in part_wires {
                 if wire != part_wire {
                     self.wire_to_nets.insert(part_wire, (new_net_id, new_index));
@@ -121,17 +1267,33 @@ impl Compiler {
                 }
             }
             net = Net::cut(net, 0, part_net, addr);
+            cut_wires.push(wire);
         }
         self.nets.insert(new_net_id, (net, new_vars));
+        self.type_step(&span, |typer, wire_types| {
+            let scheme = signature.map_err(|_| Typer::ill_typed_dependency(&name))?;
+            for (port, wire) in typer.instantiate(&scheme).into_iter().zip(cut_wires) {
+                typer.cut(port, wire_types[&wire].clone())?;
+            }
+            Ok(())
+        });
+        Ok(())
     }
 
-    fn compile_monocut(&mut self, left: Tree, right: Tree) {
+    fn compile_monocut(&mut self, left: Tree, right: Tree, span: Span) -> Result<(), CompileError> {
         match (left, right) {
-            (super::Tree::Var(a), super::Tree::Var(b)) => {
+            (super::Tree::Var(a, _), super::Tree::Var(b, _)) => {
                 // Decide whether this is a cut or a wire.
                 if let (Some((a_net, a_addr)), Some((b_net, b_addr))) =
                     (self.wire_to_nets.get(&a), self.wire_to_nets.get(&b))
                 {
+                    if a_net == b_net {
+                        return Err(self.violation(
+                            Condition::Cut,
+                            "both sides are free ports of the same net",
+                            &span,
+                        ));
+                    }
                     let a_net = self.nets.remove(&a_net).unwrap();
                     let b_net = self.nets.remove(&b_net).unwrap();
                     let new_net = Net::cut(a_net.0, *a_addr, b_net.0, *b_addr);
@@ -151,83 +1313,185 @@ impl Compiler {
                                 .insert(i, (new_net_id, new_wires.len() - 1));
                         }
                     }
+                    self.wire_to_nets.remove(&a);
+                    self.wire_to_nets.remove(&b);
                     self.nets.insert(new_net_id, (new_net, new_wires));
+                    self.type_step(&span, |typer, wire_types| {
+                        typer.cut(wire_types[&a].clone(), wire_types[&b].clone())
+                    });
                 } else if !self.wire_to_nets.contains_key(&a) && !self.wire_to_nets.contains_key(&b)
                 {
                     // Wire
-                    let new_net = Net::wire();
+                    let name = self.names.get(&a).or(self.names.get(&b));
+                    let new_net = match name {
+                        Some(name) => Net::named_wire(name),
+                        None => Net::wire(),
+                    };
+                    let [a_type, b_type] = self.typer.wire(name.map(String::as_str));
+                    self.wire_types.insert(a, a_type);
+                    self.wire_types.insert(b, b_type);
                     let new_net_id = self.make_new_net_id();
                     self.nets.insert(new_net_id, (new_net, vec![a, b]));
                     self.wire_to_nets.insert(a, (new_net_id, 0));
                     self.wire_to_nets.insert(b, (new_net_id, 1));
                 } else {
-                    panic!(
-                        "Found var monocut that is neither a cut nor a wire: {} = {}",
-                        a, b
-                    )
+                    return Err(self.violation(
+                        Condition::Wire,
+                        "only one side is a free port, so this is neither a cut nor a wire",
+                        &span,
+                    ));
                 }
             }
-            (super::Tree::Agent(agent_name, args), super::Tree::Var(var_id)) => {
-                if let Some(symbol_id) = agent_name_to_id(&agent_name) {
-                    let mut included_vars = BTreeSet::new();
-
-                    let mut graft_args = vec![];
-                    let mut new_vars = vec![var_id];
-                    let mut new_index = 1;
-                    let new_net_id = self.make_new_net_id();
-                    self.wire_to_nets.insert(var_id, (new_net_id, 0));
-                    use super::Argument;
-                    for i in args {
-                        let is_box = matches!(i, Argument::Box(..));
-                        match i {
-                            Argument::Partition(x) | Argument::Box(x) => {
-                                let mut net_id = None;
-                                let mut addresses = vec![];
-                                for wire in x {
-                                    let crate::syntax::Tree::Var(wire) = wire else {
-                                        unreachable!()
-                                    };
-                                    let (net, addr) = self.wire_to_nets.remove(&wire).unwrap();
-                                    if let Some(net_id) = net_id {
-                                        assert!(net_id == net, "Wires from the same partition were found to be from different nets!");
-                                    } else {
-                                        net_id = Some(net);
-                                    };
+            (super::Tree::Agent(agent_name, mut args, agent_span), super::Tree::Var(var_id, _)) => {
+                let Some(symbol_id) = agent_name_to_id(&agent_name) else {
+                    return Err(CompileError::UnknownSymbol {
+                        definition: self.definition.clone(),
+                        symbol: agent_name,
+                        span: agent_span,
+                    });
+                };
+                if symbol_id == SymbolId::Hole {
+                    self.holes
+                        .push((agent_name.clone(), agent_span.clone(), var_id));
+                }
+                if self.compile_implicit_context(&agent_name, &args, var_id, &agent_span)? {
+                    return Ok(());
+                }
+                use super::Argument;
+                let expected = symbol_id.args();
+                // A single partition that doesn't fit, as in `Times(a b)`, is a flat list of
+                // wires whose partitions and boxes have to be inferred.
+                let arity: usize = expected
+                    .iter()
+                    .map(|x| match x {
+                        Arg::Partition(n) | Arg::Box(n) => *n,
+                    })
+                    .sum();
+                let is_flat = matches!(&args[..], [Argument::Partition(wires, _)]
+                    if wires.len() == arity
+                        && !matches!(&expected[..], [Arg::Partition(n)] if *n == arity));
+                let args = if is_flat {
+                    let Some(Argument::Partition(wires, arg_span)) = args.pop() else {
+                        unreachable!()
+                    };
+                    self.infer_arguments(&agent_name, &expected, wires, &arg_span)?
+                } else {
+                    args
+                };
+                let matches_shape = expected.len() == args.len()
+                    && expected.iter().zip(args.iter()).all(|x| match x {
+                        (Arg::Partition(n), Argument::Partition(w, _))
+                        | (Arg::Box(n), Argument::Box(w, _)) => *n == w.len(),
+                        _ => false,
+                    });
+                if !matches_shape {
+                    return Err(self.violation(
+                        Condition::Graft,
+                        &format!(
+                            "`{}` expects arguments of the form {}",
+                            agent_name,
+                            show_shape(&agent_name, &expected)
+                        ),
+                        &agent_span,
+                    ));
+                }
+                let mut included_vars = BTreeSet::new();
 
-                                    included_vars.insert(wire);
-                                    addresses.push(addr);
-                                }
-                                let (net, old_wires) = self
-                                    .nets
-                                    .remove(&net_id.expect("No net!"))
-                                    .expect("Net for partition or box not found!");
-                                for wire in old_wires {
-                                    if !included_vars.contains(&wire) {
-                                        if is_box {
-                                            panic!("Missing wire in box!");
-                                        } else {
-                                            self.wire_to_nets.insert(wire, (new_net_id, new_index));
-                                            new_vars.push(wire);
-                                            new_index += 1;
-                                        }
+                let mut graft_args = vec![];
+                // The wires of the agent and of each of its boxes, in order.
+                let mut wires = vec![];
+                let mut boxes = vec![];
+                let mut new_vars = vec![var_id];
+                let mut new_index = 1;
+                let new_net_id = self.make_new_net_id();
+                self.wire_to_nets.insert(var_id, (new_net_id, 0));
+                for i in args {
+                    let is_box = matches!(i, Argument::Box(..));
+                    let condition = if is_box {
+                        Condition::Box
+                    } else {
+                        Condition::Graft
+                    };
+                    match i {
+                        Argument::Partition(x, arg_span) | Argument::Box(x, arg_span) => {
+                            let mut net_id = None;
+                            let mut addresses = vec![];
+                            let mut arg_wires = vec![];
+                            for wire in x {
+                                let crate::syntax::Tree::Var(wire, wire_span) = wire else {
+                                    unreachable!()
+                                };
+                                let (net, addr) = self.take_wire(wire, condition, &wire_span)?;
+                                if let Some(net_id) = net_id {
+                                    if net_id != net {
+                                        return Err(self.violation(
+                                            condition,
+                                            "wires from the same partition were found to be from different nets",
+                                            &wire_span,
+                                        ));
                                     }
-                                }
-                                if is_box {
-                                    graft_args.push(GraftArg::Box(net, addresses));
                                 } else {
-                                    graft_args.push(GraftArg::Partition(net, addresses));
+                                    net_id = Some(net);
+                                };
+
+                                included_vars.insert(wire);
+                                addresses.push(addr);
+                                arg_wires.push(wire);
+                            }
+                            let Some(net_id) = net_id else {
+                                return Err(self.violation(
+                                    condition,
+                                    "empty partition",
+                                    &arg_span,
+                                ));
+                            };
+                            let Some((net, old_wires)) = self.nets.remove(&net_id) else {
+                                return Err(self.violation(
+                                    condition,
+                                    "wires from different partitions were found to be from the same net",
+                                    &arg_span,
+                                ));
+                            };
+                            for wire in old_wires {
+                                if !included_vars.contains(&wire) {
+                                    if is_box {
+                                        return Err(self.violation(
+                                            Condition::Box,
+                                            "missing wire in box, the boxed net has other free ports",
+                                            &arg_span,
+                                        ));
+                                    } else {
+                                        self.wire_to_nets.insert(wire, (new_net_id, new_index));
+                                        new_vars.push(wire);
+                                        new_index += 1;
+                                    }
                                 }
                             }
+                            if is_box {
+                                graft_args.push(GraftArg::Box(net, addresses));
+                                boxes.push(arg_wires);
+                            } else {
+                                graft_args.push(GraftArg::Partition(net, addresses));
+                                wires.extend(arg_wires);
+                            }
                         }
                     }
-
-                    self.nets
-                        .insert(new_net_id, (Net::graft(symbol_id, graft_args), new_vars));
-                } else {
-                    unimplemented!("Unknown symbol in monocut");
                 }
+
+                self.type_step(&span, |typer, wire_types| {
+                    let mut types = |wires: Vec<usize>| -> Vec<Type> {
+                        wires.into_iter().map(|x| wire_types[&x].clone()).collect()
+                    };
+                    let boxes = boxes.into_iter().map(&mut types).collect();
+                    let principal = typer.graft(&symbol_id, types(wires), boxes)?;
+                    wire_types.insert(var_id, principal);
+                    Ok(())
+                });
+                self.nets
+                    .insert(new_net_id, (Net::graft(symbol_id, graft_args), new_vars));
             }
             (left, right) => unreachable!("{:?} {:?}", left, right),
         }
+        Ok(())
     }
 }
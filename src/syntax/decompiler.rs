@@ -0,0 +1,572 @@
+//! Turns a `Net` back into a definition, so that normal forms can be saved and used again.
+//!
+//! The net is taken apart with the simplicity operations the compiler puts together: a free
+//! port that is the principal port of a cell whose partitions are separate nets is a graft,
+//! an edge that disconnects the net is a cut, and a net without cells is a wire. The steps
+//! are written as instructions in the order the compiler needs them, with grafts nested in
+//! the trees that use them and wires left implicit where both ends go into agents.
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::compiler::AGENTS;
+use super::{Argument, Instruction, Named, Span, Tree};
+use crate::net::{Net, PartitionOrBox, SymbolId, VarId};
+use crate::util::{pick_given_name, pick_next_name};
+
+/// A definition called `name` that compiles to a net alpha-equivalent to `net`, with the same
+/// order of ports, partitions and boxes. Names of vars are kept when `net` has them.
+///
+/// This is `None` when `net` can't be built with the simplicity operations, which doesn't
+/// happen to nets that come from the compiler, nor to their reductions.
+pub fn decompile(net: &Net, name: &str) -> Option<String> {
+    let mut net = net.clone();
+    net.canonical();
+    let mut decompiler = Decompiler::default();
+    let ports = decompiler.net(&net)?;
+    let ports: Vec<Tree> = ports
+        .into_iter()
+        .map(|x| var(decompiler.materialize(x, None)))
+        .collect();
+    Some(decompiler.show(name, ports))
+}
+
+/// The end of an edge of the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum End {
+    /// A free port of the net.
+    Free,
+    Principal(usize),
+    Aux(usize),
+}
+
+impl End {
+    fn cell(self) -> Option<usize> {
+        match self {
+            End::Free => None,
+            End::Principal(c) | End::Aux(c) => Some(c),
+        }
+    }
+}
+
+/// An edge, and the index of one of its ends. As a port of a piece of the graph, it's the
+/// end outside of the piece.
+type Key = (usize, usize);
+
+struct Edge {
+    ends: [End; 2],
+    /// The name of the var, when the edge is one that has a name.
+    name: Option<String>,
+}
+
+enum CellArg<'n> {
+    /// The edges of the auxiliary ports, with the end that's at this cell.
+    Partition(Vec<Key>),
+    Box(&'n Net),
+}
+
+struct Cell<'n> {
+    symbol: SymbolId,
+    principal: usize,
+    args: Vec<CellArg<'n>>,
+}
+
+/// A net as cells and the edges between their ports. Boxes are left as they are.
+struct Graph<'n> {
+    cells: Vec<Cell<'n>>,
+    edges: Vec<Edge>,
+    /// The free ports, in order.
+    ports: Vec<Key>,
+}
+
+/// How a piece of the graph is built, with its last simplicity operation.
+enum Step {
+    /// An edge that's only connected to ports outside of the piece.
+    Wire(usize),
+    /// A cell whose principal port is the port `Key`, grafted onto a piece for each of its
+    /// partitions.
+    Graft(usize, Key, Vec<Option<Step>>),
+    /// The two pieces on each side of an edge, with the piece of its first end first.
+    Cut(usize, Box<Step>, Box<Step>),
+}
+
+/// Disjoint sets of the cells and edges of a graph, with cells numbered first.
+struct Components(Vec<usize>);
+
+impl Components {
+    fn find(&mut self, x: usize) -> usize {
+        if self.0[x] != x {
+            self.0[x] = self.find(self.0[x]);
+        }
+        self.0[x]
+    }
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.0[a] = b;
+    }
+}
+
+impl<'n> Graph<'n> {
+    fn new(net: &'n Net) -> Graph<'n> {
+        let mut graph = Graph {
+            cells: vec![],
+            edges: vec![],
+            ports: vec![],
+        };
+        let mut vars = BTreeMap::new();
+        for tree in &net.ports {
+            let key = graph.attach(net, tree, End::Free, &mut vars);
+            graph.ports.push(key);
+        }
+        for (a, b) in &net.redexes {
+            // `Net::link` never leaves a var on both sides.
+            let (a, b) = match a {
+                crate::net::Tree::Var(_) => (b, a),
+                _ => (a, b),
+            };
+            let c = graph.cell(net, a, &mut vars);
+            let (e, _) = graph.attach(net, b, End::Principal(c), &mut vars);
+            graph.cells[c].principal = e;
+        }
+        graph
+    }
+    /// Adds `tree`, connected to `end`, and returns the edge between them.
+    fn attach(
+        &mut self,
+        net: &'n Net,
+        tree: &'n crate::net::Tree,
+        end: End,
+        vars: &mut BTreeMap<VarId, usize>,
+    ) -> Key {
+        match tree {
+            crate::net::Tree::Agent(..) => {
+                let c = self.cell(net, tree, vars);
+                self.edges.push(Edge {
+                    ends: [end, End::Principal(c)],
+                    name: None,
+                });
+                self.cells[c].principal = self.edges.len() - 1;
+                (self.edges.len() - 1, 0)
+            }
+            // The first usage of a var opens an edge, and the second one closes it.
+            crate::net::Tree::Var(id) => match vars.remove(id) {
+                Some(e) => {
+                    self.edges[e].ends[1] = end;
+                    (e, 1)
+                }
+                None => {
+                    self.edges.push(Edge {
+                        ends: [end, end],
+                        name: net.names.get(id).cloned(),
+                    });
+                    vars.insert(*id, self.edges.len() - 1);
+                    (self.edges.len() - 1, 0)
+                }
+            },
+        }
+    }
+    /// Adds the cell of the agent `tree`, without connecting its principal port.
+    fn cell(
+        &mut self,
+        net: &'n Net,
+        tree: &'n crate::net::Tree,
+        vars: &mut BTreeMap<VarId, usize>,
+    ) -> usize {
+        let crate::net::Tree::Agent(symbol, args) = tree else {
+            unreachable!()
+        };
+        let c = self.cells.len();
+        self.cells.push(Cell {
+            symbol: symbol.clone(),
+            principal: usize::MAX,
+            args: vec![],
+        });
+        let mut cell_args = vec![];
+        for arg in args {
+            cell_args.push(match arg {
+                PartitionOrBox::Partition(trees) => CellArg::Partition(
+                    trees
+                        .iter()
+                        .map(|x| self.attach(net, x, End::Aux(c), vars))
+                        .collect(),
+                ),
+                PartitionOrBox::Box(net) => CellArg::Box(net),
+            });
+        }
+        self.cells[c].args = cell_args;
+        c
+    }
+    fn incident(&self, c: usize) -> impl Iterator<Item = usize> + '_ {
+        let cell = &self.cells[c];
+        let aux = cell.args.iter().flat_map(|arg| match arg {
+            CellArg::Partition(keys) => keys.iter().map(|(e, _)| *e).collect(),
+            CellArg::Box(_) => vec![],
+        });
+        std::iter::once(cell.principal).chain(aux)
+    }
+    /// The connected components of `cells`, without going through the edge `skip`.
+    fn components(&self, cells: &BTreeSet<usize>, skip: Option<usize>) -> Components {
+        let mut components = Components((0..self.cells.len() + self.edges.len()).collect());
+        for c in cells {
+            for e in self.incident(*c) {
+                if Some(e) != skip {
+                    components.union(*c, self.cells.len() + e);
+                }
+            }
+        }
+        components
+    }
+    /// How the piece made of `cells`, with the free ports `ports`, can be built.
+    fn decompose(&self, cells: &BTreeSet<usize>, ports: &[Key]) -> Option<Step> {
+        if cells.is_empty() {
+            return match ports {
+                [(a, i), (b, j)] if a == b && i != j => Some(Step::Wire(*a)),
+                _ => None,
+            };
+        }
+        for port in ports {
+            let (e, i) = *port;
+            if let End::Principal(c) = self.edges[e].ends[1 - i]
+                && cells.contains(&c)
+                && let Some(step) = self.graft(c, cells, ports, *port)
+            {
+                return Some(step);
+            }
+        }
+        // Cutting a redex comes out as `A = B`, so those are tried first.
+        let mut edges: Vec<usize> = cells
+            .iter()
+            .flat_map(|c| self.incident(*c))
+            .filter(|e| {
+                self.edges[*e]
+                    .ends
+                    .iter()
+                    .all(|x| x.cell().is_some_and(|c| cells.contains(&c)))
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        edges.sort_by_key(|e| {
+            !matches!(self.edges[*e].ends, [End::Principal(_), End::Principal(_)])
+        });
+        edges.into_iter().find_map(|e| self.cut(e, cells, ports))
+    }
+    fn graft(
+        &self,
+        c: usize,
+        cells: &BTreeSet<usize>,
+        ports: &[Key],
+        principal: Key,
+    ) -> Option<Step> {
+        let mut rest = cells.clone();
+        rest.remove(&c);
+        let mut components = self.components(&rest, None);
+        let edge = |e: usize| self.cells.len() + e;
+        let mut roots = vec![];
+        for arg in &self.cells[c].args {
+            if let CellArg::Partition(keys) = arg {
+                let root = components.find(edge(keys[0].0));
+                if roots.contains(&root)
+                    || keys.iter().any(|(e, _)| components.find(edge(*e)) != root)
+                {
+                    return None;
+                }
+                roots.push(root);
+            }
+        }
+        let ports: Vec<Key> = ports.iter().copied().filter(|x| *x != principal).collect();
+        // Everything else must hang from one of the partitions.
+        if ports
+            .iter()
+            .any(|(e, _)| !roots.contains(&components.find(edge(*e))))
+            || rest.iter().any(|d| !roots.contains(&components.find(*d)))
+        {
+            return None;
+        }
+        let mut roots = roots.into_iter();
+        let mut pieces = vec![];
+        for arg in &self.cells[c].args {
+            let CellArg::Partition(keys) = arg else {
+                pieces.push(None);
+                continue;
+            };
+            let root = roots.next().unwrap();
+            let piece_cells = rest
+                .iter()
+                .copied()
+                .filter(|d| components.find(*d) == root)
+                .collect();
+            let mut piece_ports = keys.clone();
+            piece_ports.extend(
+                ports
+                    .iter()
+                    .filter(|(e, _)| components.find(edge(*e)) == root),
+            );
+            pieces.push(Some(self.decompose(&piece_cells, &piece_ports)?));
+        }
+        Some(Step::Graft(c, principal, pieces))
+    }
+    fn cut(&self, e: usize, cells: &BTreeSet<usize>, ports: &[Key]) -> Option<Step> {
+        let mut components = self.components(cells, Some(e));
+        let [a, b] = self.edges[e].ends.map(|x| x.cell().unwrap());
+        let roots = [components.find(a), components.find(b)];
+        if roots[0] == roots[1] {
+            return None;
+        }
+        let mut pieces = vec![];
+        for (i, root) in roots.into_iter().enumerate() {
+            let piece_cells: BTreeSet<usize> = cells
+                .iter()
+                .copied()
+                .filter(|d| components.find(*d) == root)
+                .collect();
+            // The end of the edge that's outside of the piece is on the other side.
+            let mut piece_ports = vec![(e, 1 - i)];
+            piece_ports.extend(
+                ports
+                    .iter()
+                    .filter(|(e, _)| components.find(self.cells.len() + e) == root),
+            );
+            pieces.push((piece_cells, piece_ports));
+        }
+        let covered = pieces.iter().map(|(cells, _)| cells.len()).sum::<usize>() == cells.len()
+            && pieces
+                .iter()
+                .map(|(_, ports)| ports.len() - 1)
+                .sum::<usize>()
+                == ports.len();
+        if !covered {
+            return None;
+        }
+        let [(a_cells, a_ports), (b_cells, b_ports)] = <[_; 2]>::try_from(pieces).ok()?;
+        Some(Step::Cut(
+            e,
+            Box::new(self.decompose(&a_cells, &a_ports)?),
+            Box::new(self.decompose(&b_cells, &b_ports)?),
+        ))
+    }
+}
+
+fn var(id: usize) -> Tree {
+    Tree::Var(id, Span::default())
+}
+
+/// The name of `symbol` in the source, which is `_` for a hole.
+pub(crate) fn agent_name(symbol: &SymbolId) -> &'static str {
+    AGENTS
+        .iter()
+        .find(|(_, x)| x == symbol)
+        .map_or("_", |(name, _)| name)
+}
+
+/// Writes the steps of the nets it's given as instructions. Vars are numbered as they're
+/// created, and named at the end.
+#[derive(Default)]
+struct Decompiler {
+    instructions: Vec<(Tree, Tree)>,
+    /// The names from the nets, by var.
+    names: BTreeMap<usize, String>,
+    vars: usize,
+}
+
+impl Decompiler {
+    fn new_var(&mut self, name: Option<&String>) -> usize {
+        if let Some(name) = name {
+            self.names.insert(self.vars, name.clone());
+        }
+        self.vars += 1;
+        self.vars - 1
+    }
+    /// A var for `tree`, with a graft for it if it's an agent.
+    fn materialize(&mut self, tree: Tree, name: Option<&String>) -> usize {
+        match tree {
+            Tree::Var(id, _) => id,
+            agent => {
+                let id = self.new_var(name);
+                self.instructions.push((agent, var(id)));
+                id
+            }
+        }
+    }
+    /// The trees of the free ports of `net`, in order.
+    fn net(&mut self, net: &Net) -> Option<Vec<Tree>> {
+        let graph = Graph::new(net);
+        let cells = (0..graph.cells.len()).collect();
+        let step = graph.decompose(&cells, &graph.ports)?;
+        let mut trees = self.step(&graph, step)?;
+        Some(
+            graph
+                .ports
+                .iter()
+                .map(|x| trees.remove(x).unwrap())
+                .collect(),
+        )
+    }
+    /// Writes the instructions of `step`, and returns the trees of its free ports. Only the
+    /// principal port of its last graft can be an agent, since its graft can wait until the
+    /// port is used.
+    fn step(&mut self, graph: &Graph, step: Step) -> Option<BTreeMap<Key, Tree>> {
+        match step {
+            Step::Wire(e) => {
+                let a = self.new_var(graph.edges[e].name.as_ref());
+                let b = self.new_var(None);
+                self.instructions.push((var(a), var(b)));
+                Some(BTreeMap::from([((e, 0), var(a)), ((e, 1), var(b))]))
+            }
+            Step::Graft(c, principal, pieces) => {
+                let mut ports = BTreeMap::new();
+                let mut args = vec![];
+                for (arg, piece) in graph.cells[c].args.iter().zip(pieces) {
+                    match (arg, piece) {
+                        (CellArg::Partition(keys), Some(piece)) => {
+                            let mut trees = self.step(graph, piece)?;
+                            let arg = keys.iter().map(|x| trees.remove(x).unwrap()).collect();
+                            args.push(Argument::Partition(arg, Span::default()));
+                            self.pass(graph, trees, &mut ports);
+                        }
+                        (CellArg::Box(net), None) => {
+                            let arg = self.net(net)?;
+                            args.push(Argument::Box(arg, Span::default()));
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                let name = agent_name(&graph.cells[c].symbol).to_string();
+                ports.insert(principal, Tree::Agent(name, args, Span::default()));
+                Some(ports)
+            }
+            Step::Cut(e, a, b) => {
+                let mut a = self.step(graph, *a)?;
+                let mut b = self.step(graph, *b)?;
+                let mut ports = BTreeMap::new();
+                let left = a.remove(&(e, 1)).unwrap();
+                let right = b.remove(&(e, 0)).unwrap();
+                self.pass(graph, a, &mut ports);
+                self.pass(graph, b, &mut ports);
+                // An agent can only be cut with a var that's already a free port through
+                // another var.
+                let (left, right) = match (left, right) {
+                    (left @ Tree::Agent(..), right @ Tree::Var(..)) => {
+                        (var(self.materialize(left, None)), right)
+                    }
+                    (left @ Tree::Var(..), right @ Tree::Agent(..)) => {
+                        (left, var(self.materialize(right, None)))
+                    }
+                    x => x,
+                };
+                self.instructions.push((left, right));
+                Some(ports)
+            }
+        }
+    }
+    /// Moves the ports of a piece that the operation doesn't use to `ports`, grafting the
+    /// agents among them now.
+    fn pass(&mut self, graph: &Graph, trees: BTreeMap<Key, Tree>, ports: &mut BTreeMap<Key, Tree>) {
+        for ((e, i), tree) in trees {
+            let id = self.materialize(tree, graph.edges[e].name.as_ref());
+            ports.insert((e, i), var(id));
+        }
+    }
+    fn show(mut self, name: &str, ports: Vec<Tree>) -> String {
+        // A wire whose ends go into agents is declared by the first of them, as in
+        // `Par(a a)`. So is one with an end in the ports, which the parser reads last.
+        let mut in_agents = BTreeSet::new();
+        for (a, b) in &self.instructions {
+            for tree in [a, b] {
+                if let Tree::Agent(_, args, _) = tree {
+                    vars_of_arguments(args, &mut in_agents);
+                }
+            }
+        }
+        let in_ports = |x: &usize| ports.iter().any(|y| matches!(y, Tree::Var(y, _) if y == x));
+        let mut same = BTreeMap::new();
+        self.instructions.retain(|x| match x {
+            (Tree::Var(a, _), Tree::Var(b, _))
+                if (in_agents.contains(a) || in_agents.contains(b))
+                    && [a, b].iter().all(|x| in_agents.contains(x) || in_ports(x)) =>
+            {
+                same.insert(*b, *a);
+                false
+            }
+            _ => true,
+        });
+        let mut ports = ports;
+        ports.iter_mut().for_each(|x| rename(x, &same));
+        let instructions: Vec<Instruction> = self
+            .instructions
+            .into_iter()
+            .map(|(mut a, mut b)| {
+                rename(&mut a, &same);
+                rename(&mut b, &same);
+                Instruction::Monocut(a, b, Span::default())
+            })
+            .collect();
+        // Vars are named in order of appearance, the ones from the net first.
+        let mut order = vec![];
+        for tree in &ports {
+            vars_of_tree(tree, &mut order);
+        }
+        for i in &instructions {
+            if let Instruction::Monocut(a, b, _) = i {
+                vars_of_tree(a, &mut order);
+                vars_of_tree(b, &mut order);
+            }
+        }
+        let mut scope = BTreeMap::new();
+        for id in &order {
+            if let Some(name) = self.names.get(id) {
+                pick_given_name(&mut scope, *id, name);
+            }
+        }
+        for id in &order {
+            pick_next_name(&mut scope, *id);
+        }
+        let header = Argument::Partition(ports, Span::default());
+        let mut out = format!("{}{} {{\n", name, Named(&header, &scope));
+        for i in &instructions {
+            out += &format!("  {}\n", Named(i, &scope));
+        }
+        out + "}\n"
+    }
+}
+
+fn rename(tree: &mut Tree, same: &BTreeMap<usize, usize>) {
+    match tree {
+        Tree::Var(id, _) => *id = *same.get(id).unwrap_or(id),
+        Tree::Agent(_, args, _) => {
+            for arg in args {
+                let (Argument::Partition(trees, _) | Argument::Box(trees, _)) = arg;
+                trees.iter_mut().for_each(|x| rename(x, same));
+            }
+        }
+    }
+}
+
+fn vars_of_arguments(args: &[Argument], vars: &mut BTreeSet<usize>) {
+    for arg in args {
+        let (Argument::Partition(trees, _) | Argument::Box(trees, _)) = arg;
+        for tree in trees {
+            match tree {
+                Tree::Var(id, _) => {
+                    vars.insert(*id);
+                }
+                Tree::Agent(_, args, _) => vars_of_arguments(args, vars),
+            }
+        }
+    }
+}
+
+/// Adds the vars of `tree` to `order`, if they aren't there yet.
+fn vars_of_tree(tree: &Tree, order: &mut Vec<usize>) {
+    match tree {
+        Tree::Var(id, _) => {
+            if !order.contains(id) {
+                order.push(*id);
+            }
+        }
+        Tree::Agent(_, args, _) => {
+            for arg in args {
+                let (Argument::Partition(trees, _) | Argument::Box(trees, _)) = arg;
+                trees.iter().for_each(|x| vars_of_tree(x, order));
+            }
+        }
+    }
+}
@@ -7,6 +7,7 @@ use crate::syntax::Argument;
 
 use crate::syntax::Tree;
 
+use crate::syntax::Diagnostic;
 use crate::syntax::Instruction;
 
 pub struct Desugarer {
@@ -15,12 +16,15 @@ pub struct Desugarer {
     // maps old vars to RHS of new wired vars
     validly_declared_vars: BTreeSet<usize>,
     new_wired_vars: BTreeMap<usize, usize>,
+    /// Source names of vars. Auto-declared vars take the name of the var they're wired to.
+    pub names: BTreeMap<usize, String>,
 }
 
 impl Desugarer {
-    pub fn new(new_var: usize) -> Self {
+    pub fn new(new_var: usize, names: BTreeMap<usize, String>) -> Self {
         Self {
             new_var,
+            names,
             validly_declared_vars: BTreeSet::new(),
             new_wired_vars: BTreeMap::new(),
             output: vec![],
@@ -33,43 +37,52 @@ impl Desugarer {
     pub fn desugar_contents(&mut self, t: Vec<Argument>) -> Vec<Argument> {
         t.into_iter()
             .map(|x| match x {
-                Argument::Partition(u) => {
-                    Argument::Partition(u.into_iter().map(|v| self.desugar(v)).collect())
+                Argument::Partition(u, span) => {
+                    Argument::Partition(u.into_iter().map(|v| self.desugar(v)).collect(), span)
+                }
+                Argument::Box(u, span) => {
+                    Argument::Box(u.into_iter().map(|v| self.desugar(v)).collect(), span)
                 }
-                Argument::Box(u) => Argument::Box(u.into_iter().map(|v| self.desugar(v)).collect()),
             })
             .collect()
     }
     fn desugar(&mut self, t: Tree) -> Tree {
         match t {
-            Tree::Var(id) => {
+            Tree::Var(id, span) => {
                 if self.validly_declared_vars.contains(&id) {
                     self.validly_declared_vars.remove(&id);
-                    Tree::Var(id)
+                    Tree::Var(id, span)
                 } else {
                     // See if the variable has been auto-declared
                     if let Some(id) = self.new_wired_vars.remove(&id) {
-                        Tree::Var(id)
+                        Tree::Var(id, span)
                     } else {
                         // Auto-declare the variable with a wire link.
                         let new_id = self.make_new_var();
-                        self.output
-                            .push(Instruction::Monocut(Tree::Var(id), Tree::Var(new_id)));
+                        if let Some(name) = self.names.get(&id) {
+                            self.names.insert(new_id, name.clone());
+                        }
+                        self.output.push(Instruction::Monocut(
+                            Tree::Var(id, span.clone()),
+                            Tree::Var(new_id, span.clone()),
+                            span.clone(),
+                        ));
                         self.validly_declared_vars.insert(new_id);
                         self.new_wired_vars.insert(id, new_id);
-                        Tree::Var(id)
+                        Tree::Var(id, span)
                     }
                 }
             }
-            Tree::Agent(id, args) => {
+            Tree::Agent(id, args, span) => {
                 let new_var = self.make_new_var();
                 let o = Instruction::Monocut(
-                    Tree::Agent(id, self.desugar_contents(args)),
-                    Tree::Var(new_var),
+                    Tree::Agent(id, self.desugar_contents(args), span.clone()),
+                    Tree::Var(new_var, span.clone()),
+                    span.clone(),
                 );
                 self.output.push(o);
                 self.validly_declared_vars.insert(new_var);
-                Tree::Var(new_var)
+                Tree::Var(new_var, span)
             }
         }
     }
@@ -80,43 +93,46 @@ impl Desugarer {
             id
         }
     }
-    pub fn desugar_instr(&mut self, instr: Instruction) {
+    pub fn desugar_instr(&mut self, instr: Instruction) -> Result<(), Diagnostic> {
         match instr {
-            Instruction::Multicut(name, args) => {
+            Instruction::Multicut(name, args, span) => {
                 let args = args
                     .into_iter()
                     .map(|x| {
-                        let Tree::Var(x) = self.desugar(x) else {
+                        let Tree::Var(x, span) = self.desugar(x) else {
                             unreachable!()
                         };
                         self.validly_declared_vars.insert(x);
-                        Tree::Var(self.dealias_var(x))
+                        Tree::Var(self.dealias_var(x), span)
                     })
                     .collect();
-                self.output.push(Instruction::Multicut(name, args))
+                self.output.push(Instruction::Multicut(name, args, span))
             }
-            Instruction::Monocut(left @ Tree::Var(idl), right @ Tree::Var(idr)) => {
+            Instruction::Monocut(left @ Tree::Var(idl, _), right @ Tree::Var(idr, _), span) => {
                 self.validly_declared_vars.insert(idl);
                 self.validly_declared_vars.insert(idr);
-                self.output.push(Instruction::Monocut(left, right))
+                self.output.push(Instruction::Monocut(left, right, span))
             }
-            Instruction::Monocut(Tree::Var(_id), Tree::Agent(_aid, _args)) => {
-                panic!("Invalid syntax: var = agent")
+            Instruction::Monocut(left @ Tree::Var(..), right @ Tree::Agent(..), span) => {
+                // `var = agent` is the same graft as `agent = var`.
+                return self.desugar_instr(Instruction::Monocut(right, left, span));
             }
-            Instruction::Monocut(Tree::Agent(aid, args), Tree::Var(vid)) => {
+            Instruction::Monocut(Tree::Agent(aid, args, aspan), Tree::Var(vid, vspan), span) => {
                 // Graft, but needs desugaring contents of LHS
                 self.validly_declared_vars.insert(vid);
                 let o = Instruction::Monocut(
-                    Tree::Agent(aid, self.desugar_contents(args)),
-                    Tree::Var(self.dealias_var(vid)),
+                    Tree::Agent(aid, self.desugar_contents(args), aspan),
+                    Tree::Var(self.dealias_var(vid), vspan),
+                    span,
                 );
                 self.output.push(o);
             }
-            Instruction::Monocut(left @ Tree::Agent(..), right @ Tree::Agent(..)) => {
+            Instruction::Monocut(left @ Tree::Agent(..), right @ Tree::Agent(..), span) => {
                 // Cut, but needs desugaring
-                let o = Instruction::Monocut(self.desugar(left), self.desugar(right));
+                let o = Instruction::Monocut(self.desugar(left), self.desugar(right), span);
                 self.output.push(o);
             }
         }
+        Ok(())
     }
 }
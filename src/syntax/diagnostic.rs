@@ -0,0 +1,85 @@
+// Error reporting for the syntax pipeline.
+use crate::syntax::Span;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            message: message.into(),
+            span,
+        }
+    }
+    /// Renders the diagnostic with the line of `source` it points at, like:
+    ///
+    /// ```text
+    /// error: Not an argument!
+    ///  --> main.line:3:12
+    ///   |
+    /// 3 |   Times(a)(b = c
+    ///   |            ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        self.render_as("error", source)
+    }
+    /// Like `render`, with another word than `error` in front, as in `hole: ...`.
+    pub fn render_as(&self, level: &str, source: &str) -> String {
+        use std::fmt::Write;
+        let (line, column) = line_column(source, self.span.start);
+        let line_start = source[..self.span.start.min(source.len())]
+            .rfind('\n')
+            .map_or(0, |x| x + 1);
+        let line_text = source[line_start..].lines().next().unwrap_or("");
+        let number = (line + 1).to_string();
+        let gutter = " ".repeat(number.len());
+        // The caret covers the span, but never runs past the end of the line.
+        let width = self
+            .span
+            .end
+            .min(line_start + line_text.len())
+            .saturating_sub(self.span.start)
+            .max(1);
+        let mut s = String::new();
+        writeln!(&mut s, "{}: {}", level, self.message).unwrap();
+        writeln!(
+            &mut s,
+            "{}--> {}:{}:{}",
+            gutter,
+            self.span.file,
+            line + 1,
+            column + 1
+        )
+        .unwrap();
+        writeln!(&mut s, "{} |", gutter).unwrap();
+        writeln!(&mut s, "{} | {}", number, line_text).unwrap();
+        write!(
+            &mut s,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(column),
+            "^".repeat(width)
+        )
+        .unwrap();
+        s
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}: {}", self.span.file, self.message)
+    }
+}
+
+/// Zero-based line and column (in characters) of a byte offset.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count();
+    let column = before[before.rfind('\n').map_or(0, |x| x + 1)..]
+        .chars()
+        .count();
+    (line, column)
+}
@@ -0,0 +1,309 @@
+//! Prints a book in a canonical layout, keeping its comments and the names it uses.
+//!
+//! The formatter prints the tokens that `parser` records as it reads the book, instead of the
+//! trees it builds, so operators, names and formulas are kept as they were written. Only the
+//! whitespace changes, and comments inside of an instruction are moved in front of it, so the
+//! book compiles to the same nets.
+//!
+//! The layout is:
+//! - One instruction per line, indented by two spaces, and a blank line around definitions.
+//! - Blank lines between other lines are kept, but never more than one in a row.
+//! - Single spaces around `=` and binary operators, and between the trees of a partition.
+//! - Ports separated by spaces, or by commas when some of them have a type.
+use std::collections::BTreeMap;
+
+use crate::syntax::parser::{ElementKind, Layout, Parser, Token, TokenKind};
+use crate::syntax::Diagnostic;
+
+/// `source` in the canonical layout. Errors are those of `parser::parse_file`.
+pub fn format(source: &str, file: &str) -> Result<String, Diagnostic> {
+    let mut parser = Parser::new(source, file);
+    parser.parse_book()?;
+    let layout = parser.into_layout();
+    let comments = layout
+        .comments
+        .iter()
+        .map(|(start, end)| (*start, &source[*start..*end]))
+        .collect();
+    Ok(Formatter {
+        input: source,
+        layout,
+        comments,
+    }
+    .book())
+}
+
+struct Formatter<'i> {
+    input: &'i str,
+    layout: Layout,
+    /// The comments of the book, by offset, without the line break.
+    comments: BTreeMap<usize, &'i str>,
+}
+
+/// An item of the book or an instruction of a definition, printed.
+struct Element {
+    start: usize,
+    end: usize,
+    text: String,
+    definition: bool,
+}
+
+/// An element or a comment on its own line, with the comments that follow it on its line.
+struct Line<'a> {
+    start: usize,
+    end: usize,
+    text: &'a str,
+    trailing: Option<&'a str>,
+    /// Whether it's an element, and whether that's a definition.
+    element: Option<bool>,
+}
+
+impl<'i> Formatter<'i> {
+    fn book(&self) -> String {
+        let mut elements = vec![];
+        // The bodies of the definitions, whose comments are laid out with their instructions.
+        let mut bodies = vec![];
+        for element in &self.layout.elements {
+            let text = match element.kind {
+                ElementKind::Instruction => continue,
+                ElementKind::Definition => self.definition(element.start, element.end, &mut bodies),
+                ElementKind::Import | ElementKind::Alias => {
+                    self.print(self.tokens(element.start, element.end))
+                }
+            };
+            elements.push(Element {
+                start: element.start,
+                end: element.end,
+                text,
+                definition: element.kind == ElementKind::Definition,
+            });
+        }
+        let comments = self
+            .comments
+            .iter()
+            .filter(|(x, _)| !bodies.iter().any(|(start, end)| start < *x && *x < end))
+            .map(|(x, comment)| (*x, *comment))
+            .collect();
+        let mut book = self.layout(&elements, comments, "", true);
+        if !book.is_empty() {
+            book.push('\n');
+        }
+        book
+    }
+    fn definition(&self, start: usize, end: usize, bodies: &mut Vec<(usize, usize)>) -> String {
+        let tokens = self.tokens(start, end);
+        let open = tokens
+            .iter()
+            .position(|x| x.kind == TokenKind::Brace)
+            .expect("a definition has a body");
+        let header = self.print(&tokens[..open]);
+        let (body_start, body_end) = (tokens[open].end, tokens[tokens.len() - 1].start);
+        bodies.push((body_start, body_end));
+        let instructions: Vec<Element> = self
+            .layout
+            .elements
+            .iter()
+            .filter(|x| {
+                x.kind == ElementKind::Instruction && body_start <= x.start && x.end <= body_end
+            })
+            .map(|x| Element {
+                start: x.start,
+                end: x.end,
+                text: self.print(self.tokens(x.start, x.end)),
+                definition: false,
+            })
+            .collect();
+        let comments: Vec<(usize, &str)> = self
+            .comments
+            .range(body_start..body_end)
+            .map(|(x, comment)| (*x, *comment))
+            .collect();
+        if instructions.is_empty() && comments.is_empty() {
+            return format!("{} {{}}", header);
+        }
+        let body = self.layout(&instructions, comments, "  ", false);
+        format!("{} {{\n{}\n}}", header, body)
+    }
+
+    /// The tokens from `start` to `end`.
+    fn tokens(&self, start: usize, end: usize) -> &[Token] {
+        let tokens = &self.layout.tokens;
+        // Tokens are recorded in the order they're read.
+        let from = tokens.partition_point(|x| x.start < start);
+        let to = tokens.partition_point(|x| x.start < end);
+        &tokens[from..to]
+    }
+    /// Prints `tokens` on one line, spaced by their kinds, except for arguments that would
+    /// read otherwise on one line.
+    fn print(&self, tokens: &[Token]) -> String {
+        // Ports are separated by commas when some of them have a type.
+        let typed = tokens.iter().any(|x| x.kind == TokenKind::Colon);
+        let mut out = String::new();
+        let mut before = None;
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = &tokens[i];
+            let text = match token.kind {
+                TokenKind::Item => {
+                    i += 1;
+                    continue;
+                }
+                TokenKind::Separator if !typed => {
+                    i += 1;
+                    continue;
+                }
+                TokenKind::Comma | TokenKind::Separator => ",",
+                _ => &self.input[token.start..token.end],
+            };
+            if let Some(before) = before
+                && spaced(before, token.kind)
+            {
+                out.push(' ');
+            }
+            if token.kind == TokenKind::Open
+                && let Some((trees, close)) = self.argument(tokens, i)
+            {
+                // A `?` on the same line as the tree before it would make a contraction of the two.
+                let trees: Vec<String> = trees.iter().map(|x| indent(x, "  ")).collect();
+                let close_text = &self.input[tokens[close].start..tokens[close].end];
+                out += &format!("{}\n{}\n{}", text, trees.join("\n"), close_text);
+                before = Some(TokenKind::Close);
+                i = close + 1;
+                continue;
+            }
+            out += text;
+            before = Some(token.kind);
+            i += 1;
+        }
+        out
+    }
+    /// The trees of the argument that opens at `tokens[open]` and the index of its end, if some
+    /// tree but the first starts with a `?`.
+    fn argument(&self, tokens: &[Token], open: usize) -> Option<(Vec<String>, usize)> {
+        let mut depth = 0;
+        let mut items = vec![];
+        let mut close = None;
+        for (i, token) in tokens.iter().enumerate().skip(open + 1) {
+            match token.kind {
+                TokenKind::Open | TokenKind::Paren => depth += 1,
+                TokenKind::Close if depth == 0 => {
+                    close = Some(i);
+                    break;
+                }
+                TokenKind::Close => depth -= 1,
+                TokenKind::Item if depth == 0 => items.push(i),
+                _ => (),
+            }
+        }
+        let close = close?;
+        let trees: Vec<String> = items
+            .iter()
+            .enumerate()
+            .map(|(k, start)| {
+                let end = items.get(k + 1).copied().unwrap_or(close);
+                self.print(&tokens[start + 1..end])
+            })
+            .collect();
+        trees
+            .iter()
+            .skip(1)
+            .any(|x| x.starts_with('?'))
+            .then_some((trees, close))
+    }
+
+    /// Lays out `elements` one per line, along with `comments`. Comments that follow an element
+    /// on its line stay there, and those inside of an element are put in front of it.
+    /// At the top level, definitions are surrounded by blank lines.
+    fn layout(
+        &self,
+        elements: &[Element],
+        comments: Vec<(usize, &'i str)>,
+        indentation: &str,
+        top: bool,
+    ) -> String {
+        // Lines are sorted by where they go, and comments go before the elements they're in.
+        let mut lines: Vec<((usize, usize), Line)> = elements
+            .iter()
+            .map(|x| {
+                let line = Line {
+                    start: x.start,
+                    end: x.end,
+                    text: &x.text,
+                    trailing: None,
+                    element: Some(x.definition),
+                };
+                ((x.start, usize::MAX), line)
+            })
+            .collect();
+        for (offset, comment) in comments {
+            let end = offset + comment.len();
+            if let Some(element) = elements.iter().find(|x| x.start < offset && offset < x.end) {
+                // It's separated from the line before as the element is.
+                let line = Line {
+                    start: element.start,
+                    end,
+                    text: comment,
+                    trailing: None,
+                    element: None,
+                };
+                lines.push(((element.start, offset), line));
+            } else if let Some(((_, _), line)) = lines.iter_mut().find(|(_, x)| {
+                x.element.is_some() && x.end <= offset && !self.input[x.end..offset].contains('\n')
+            }) {
+                line.trailing = Some(comment);
+                line.end = end;
+            } else {
+                let line = Line {
+                    start: offset,
+                    end,
+                    text: comment,
+                    trailing: None,
+                    element: None,
+                };
+                lines.push(((offset, 0), line));
+            }
+        }
+        lines.sort_by_key(|(key, _)| *key);
+        let mut out = String::new();
+        for (i, (_, line)) in lines.iter().enumerate() {
+            if i > 0 {
+                let before = &lines[i - 1].1;
+                let gap = self.input.get(before.end..line.start).unwrap_or("");
+                let blank = gap.trim().is_empty() && gap.matches('\n').count() > 1;
+                // The first line after an element, when that element or the next one is a
+                // definition.
+                let around_definition = top
+                    && before.element.is_some()
+                    && (before.element == Some(true)
+                        || lines[i..].iter().find_map(|(_, x)| x.element) == Some(true));
+                out.push('\n');
+                if blank || around_definition {
+                    out.push('\n');
+                }
+            }
+            out += &indent(line.text, indentation);
+            if let Some(comment) = line.trailing {
+                out += &format!(" {}", comment);
+            }
+        }
+        out
+    }
+}
+
+/// `text` with `indentation` in front of each line.
+fn indent(text: &str, indentation: &str) -> String {
+    text.lines()
+        .map(|x| format!("{}{}", indentation, x))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether there's a space between tokens of these kinds.
+fn spaced(before: TokenKind, after: TokenKind) -> bool {
+    use TokenKind::*;
+    !matches!(before, Open | Paren | Prefix | Binder)
+        && !matches!(
+            after,
+            Open | Close | Postfix | Dot | Colon | Comma | Separator
+        )
+}
@@ -1,129 +1,242 @@
+use crate::types::Type;
+pub use diagnostic::Diagnostic;
+use std::collections::BTreeMap;
+
+/// A byte range in a source file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Span {
     pub start: usize,
     pub end: usize,
     pub file: String,
 }
 
-#[derive(Debug)]
+impl Span {
+    pub fn new(start: usize, end: usize, file: &str) -> Span {
+        Span {
+            start,
+            end,
+            file: file.to_string(),
+        }
+    }
+    /// Smallest span that covers both `self` and `other`.
+    pub fn join(&self, other: &Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+            file: self.file.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Argument {
-    Partition(Vec<Tree>),
-    Box(Vec<Tree>),
+    Partition(Vec<Tree>, Span),
+    Box(Vec<Tree>, Span),
 }
 
-#[derive(Debug)]
+impl Argument {
+    pub fn span(&self) -> &Span {
+        match self {
+            Argument::Partition(_, span) | Argument::Box(_, span) => span,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Tree {
-    Agent(String, Vec<Argument>),
-    Var(usize),
+    Agent(String, Vec<Argument>, Span),
+    Var(usize, Span),
 }
 
 impl Tree {
     pub fn is_var(&self) -> bool {
         matches!(self, Tree::Var(..))
     }
+    pub fn span(&self) -> &Span {
+        match self {
+            Tree::Agent(_, _, span) | Tree::Var(_, span) => span,
+        }
+    }
 }
 
-pub type Book = Vec<AstNet>;
+#[derive(Debug, Default)]
+pub struct Book {
+    pub imports: Vec<Import>,
+    /// Named formulas, as in `type Bool = 1 ⊕ 1`.
+    pub aliases: Vec<crate::types::alias::Alias>,
+    pub nets: Vec<AstNet>,
+}
 
+/// `import "path.line" as namespace`. Without `as`, the namespace is the file's stem.
 #[derive(Debug)]
+pub struct Import {
+    pub path: String,
+    pub namespace: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
 pub enum Instruction {
-    Multicut(String, Vec<Tree>),
-    Monocut(Tree, Tree),
+    Multicut(String, Vec<Tree>, Span),
+    Monocut(Tree, Tree, Span),
 }
 
-#[derive(Debug)]
+impl Instruction {
+    pub fn span(&self) -> &Span {
+        match self {
+            Instruction::Multicut(_, _, span) | Instruction::Monocut(_, _, span) => span,
+        }
+    }
+}
+
+/// Declared types of the ports of a definition, as in `Foo(a: A', b: A)`, with their spans.
+pub type Signature = Vec<Option<(Type, Span)>>;
+
+#[derive(Debug, Clone)]
 pub struct AstNet {
-    name: String,
-    outputs: Vec<Argument>,
-    instructions: Vec<Instruction>,
+    pub(crate) name: String,
+    pub(crate) outputs: Vec<Argument>,
+    pub(crate) instructions: Vec<Instruction>,
+    /// Source names of the vars that have one.
+    pub(crate) names: BTreeMap<usize, String>,
+    pub(crate) port_types: Signature,
+    /// Names of the propositional variables in `port_types`.
+    pub(crate) type_names: BTreeMap<usize, String>,
+    pub(crate) span: Span,
+}
+
+impl AstNet {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+    /// The definitions this one refers to through multicuts, with the span of each multicut.
+    pub fn references(&self) -> impl Iterator<Item = (&str, &Span)> + '_ {
+        self.instructions.iter().filter_map(|i| match i {
+            Instruction::Multicut(name, _, span) => Some((name.as_str(), span)),
+            Instruction::Monocut(..) => None,
+        })
+    }
+    /// Moves the definition into `namespace`, along with the definitions it refers to.
+    pub fn add_namespace(&mut self, namespace: &str) {
+        self.name = format!("{}::{}", namespace, self.name);
+        for i in &mut self.instructions {
+            if let Instruction::Multicut(name, _, _) = i {
+                *name = format!("{}::{}", namespace, name);
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Import {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "import {:?} as {}", self.path, self.namespace)
+    }
 }
 
 impl std::fmt::Display for AstNet {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(f, "{}", self.name)?;
         for i in &self.outputs {
-            write!(f, "{}", i)?;
+            write!(f, "{}", Named(i, &self.names))?;
         }
         write!(f, " {{\n")?;
         for i in &self.instructions {
-            write!(f, "    {}\n", i)?;
+            write!(f, "    {}\n", Named(i, &self.names))?;
         }
         write!(f, "}}")?;
         Ok(())
     }
 }
 
-impl std::fmt::Display for Instruction {
+/// Displays syntax with the source names of its vars. Vars without a name get a generated one.
+pub struct Named<'a, T>(pub &'a T, pub &'a BTreeMap<usize, String>);
+
+impl std::fmt::Display for Named<'_, Instruction> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        match self {
-            Instruction::Multicut(name, trees) => {
+        let names = self.1;
+        match self.0 {
+            Instruction::Multicut(name, trees, _) => {
                 write!(f, "{}(", name)?;
                 let mut sp = false;
                 for i in trees {
                     if sp {
                         write!(f, " ")?;
                     }
-                    write!(f, "{}", i)?;
+                    write!(f, "{}", Named(i, names))?;
                     sp = true
                 }
                 write!(f, ")")?;
             }
-            Instruction::Monocut(l, r) => {
-                write!(f, "{} = {}", l, r)?;
+            Instruction::Monocut(l, r, _) => {
+                write!(f, "{} = {}", Named(l, names), Named(r, names))?;
             }
         };
         Ok(())
     }
 }
-impl std::fmt::Display for Argument {
+impl std::fmt::Display for Named<'_, Argument> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        match self {
-            Argument::Partition(trees) => {
-                write!(f, "(")?;
-                let mut sp = false;
-                for i in trees {
-                    if sp {
-                        write!(f, " ")?;
-                    }
-                    write!(f, "{}", i)?;
-                    sp = true
-                }
-                write!(f, ")")?;
-            }
-            Argument::Box(trees) => {
-                write!(f, "[")?;
-                let mut sp = false;
-                for i in trees {
-                    if sp {
-                        write!(f, " ")?;
-                    }
-                    write!(f, "{}", i)?;
-                    sp = true
-                }
-                write!(f, "]")?;
-            }
+        let names = self.1;
+        let (open, trees, close) = match self.0 {
+            Argument::Partition(trees, _) => ("(", trees, ")"),
+            Argument::Box(trees, _) => ("[", trees, "]"),
         };
+        write!(f, "{}", open)?;
+        let mut sp = false;
+        for i in trees {
+            if sp {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", Named(i, names))?;
+            sp = true
+        }
+        write!(f, "{}", close)?;
         Ok(())
     }
 }
-impl std::fmt::Display for Tree {
+impl std::fmt::Display for Named<'_, Tree> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        match self {
-            Tree::Agent(name, args) => {
+        let names = self.1;
+        match self.0 {
+            Tree::Agent(name, args, _) => {
                 write!(f, "{}", name)?;
                 for i in args {
-                    write!(f, "{}", i)?;
+                    write!(f, "{}", Named(i, names))?;
                 }
             }
-            Tree::Var(id) => {
-                write!(f, "{}", crate::util::number_to_string(*id))?;
-            }
+            Tree::Var(id, _) => match names.get(id) {
+                Some(name) => write!(f, "{}", name)?,
+                None => write!(f, "{}", crate::util::number_to_string(*id))?,
+            },
         };
         Ok(())
     }
 }
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", Named(self, &BTreeMap::new()))
+    }
+}
+impl std::fmt::Display for Argument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", Named(self, &BTreeMap::new()))
+    }
+}
+impl std::fmt::Display for Tree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", Named(self, &BTreeMap::new()))
+    }
+}
 
 pub mod compiler;
+pub mod decompiler;
 pub mod desugarer;
+pub mod diagnostic;
+pub mod format;
+pub mod module;
 pub mod parser;
 
 pub use parser::Parser;
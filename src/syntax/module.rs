@@ -0,0 +1,164 @@
+// Loads a book along with the books it imports.
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::syntax::parser::parse_file_with_aliases;
+use crate::syntax::Book;
+use crate::syntax::Diagnostic;
+use crate::syntax::Import;
+use crate::types::alias::Alias;
+
+/// Environment variable with more directories to look for imports in, separated like `PATH`.
+pub const SEARCH_PATH_VAR: &str = "LINEAR_NETS_PATH";
+
+#[derive(Debug, Default)]
+pub struct Loader {
+    /// Directories where imports are looked for, after the directory of the importing file.
+    pub search_path: Vec<PathBuf>,
+    /// Contents of every file that was read, by the file name used in its spans.
+    pub sources: BTreeMap<String, String>,
+    // Files that are being loaded, outermost first, to detect import cycles.
+    stack: Vec<(PathBuf, String)>,
+}
+
+impl Loader {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            search_path,
+            ..Default::default()
+        }
+    }
+    /// A loader that uses the search path in `LINEAR_NETS_PATH`.
+    pub fn from_env() -> Self {
+        let search_path = std::env::var_os(SEARCH_PATH_VAR)
+            .map(|x| std::env::split_paths(&x).collect())
+            .unwrap_or_default();
+        Self::new(search_path)
+    }
+    /// The contents of `file`, for rendering diagnostics that point into it.
+    pub fn source(&self, file: &str) -> &str {
+        self.sources.get(file).map_or("", |x| x.as_str())
+    }
+    /// Parses `source` and everything it imports. Imports are looked for in `dir` first.
+    ///
+    /// The definitions and aliases of an import are moved into its namespace and put before
+    /// those of the importing book, so that they are compiled first.
+    pub fn load_source(
+        &mut self,
+        file: &str,
+        source: String,
+        dir: &Path,
+    ) -> Result<Book, Diagnostic> {
+        self.load(file, source, dir.join(file), &[])
+    }
+    /// Like `load_source`, but the formulas of `source` can also use `aliases`, which were
+    /// declared before it. Those of its imports can't.
+    pub fn load_source_with_aliases(
+        &mut self,
+        file: &str,
+        source: String,
+        dir: &Path,
+        aliases: &[Alias],
+    ) -> Result<Book, Diagnostic> {
+        self.load(file, source, dir.join(file), aliases)
+    }
+    fn load(
+        &mut self,
+        file: &str,
+        source: String,
+        path: PathBuf,
+        aliases: &[Alias],
+    ) -> Result<Book, Diagnostic> {
+        let book = parse_file_with_aliases(&source, file, aliases);
+        self.sources.insert(file.to_string(), source);
+        let mut book = book?;
+        let path = std::fs::canonicalize(&path).unwrap_or(path);
+        self.stack.push((path.clone(), file.to_string()));
+        let imported = self.load_imports(&book, file, &path);
+        self.stack.pop();
+        let mut imported = imported?;
+        imported.nets.append(&mut book.nets);
+        book.nets = imported.nets;
+        imported.aliases.append(&mut book.aliases);
+        book.aliases = imported.aliases;
+        Ok(book)
+    }
+    fn load_imports(&mut self, book: &Book, file: &str, path: &Path) -> Result<Book, Diagnostic> {
+        let mut imported = Book::default();
+        for import in &book.imports {
+            let book = self.load_import(import, file, path)?;
+            for mut net in book.nets {
+                net.add_namespace(&import.namespace);
+                imported.nets.push(net);
+            }
+            for mut alias in book.aliases {
+                alias.name = format!("{}::{}", import.namespace, alias.name);
+                imported.aliases.push(alias);
+            }
+        }
+        Ok(imported)
+    }
+    fn load_import(
+        &mut self,
+        import: &Import,
+        file: &str,
+        path: &Path,
+    ) -> Result<Book, Diagnostic> {
+        let (import_path, import_file) = self.resolve(import, file, path)?;
+        let canonical = std::fs::canonicalize(&import_path).unwrap_or(import_path.clone());
+        if let Some(i) = self.stack.iter().position(|(x, _)| *x == canonical) {
+            let cycle = self.stack[i..]
+                .iter()
+                .map(|(_, x)| x.clone())
+                .chain([import_file.clone()]);
+            return Err(Diagnostic::new(
+                format!(
+                    "Import cycle: {}",
+                    crate::util::join_with(cycle, " -> ".to_string())
+                ),
+                import.span.clone(),
+            ));
+        }
+        let source = match std::fs::read_to_string(&import_path) {
+            Ok(source) => source,
+            Err(e) => {
+                return Err(Diagnostic::new(
+                    format!("Can't read `{}`: {}", import_path.display(), e),
+                    import.span.clone(),
+                ));
+            }
+        };
+        self.load(&import_file, source, import_path, &[])
+    }
+    /// Finds the file of `import`, which appears in `file`, read from `path`.
+    /// Returns its path and the name to use for it in spans.
+    fn resolve(
+        &self,
+        import: &Import,
+        file: &str,
+        path: &Path,
+    ) -> Result<(PathBuf, String), Diagnostic> {
+        let candidate = path.parent().unwrap_or(Path::new("")).join(&import.path);
+        if candidate.is_file() {
+            let name = match Path::new(file).parent() {
+                Some(parent) => parent.join(&import.path).display().to_string(),
+                None => import.path.clone(),
+            };
+            return Ok((candidate, name));
+        }
+        for dir in &self.search_path {
+            let candidate = dir.join(&import.path);
+            if candidate.is_file() {
+                return Ok((candidate, import.path.clone()));
+            }
+        }
+        Err(Diagnostic::new(
+            format!(
+                "Can't find `{}` next to `{}` or in the search path",
+                import.path, file
+            ),
+            import.span.clone(),
+        ))
+    }
+}
@@ -1,12 +1,18 @@
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use TSPL::Parser as TSPLParser;
 
 use crate::syntax::desugarer::Desugarer;
 
 use crate::syntax::AstNet;
 
+use crate::syntax::Diagnostic;
 use crate::syntax::Instruction;
+use crate::syntax::Signature;
+use crate::syntax::Span;
 use crate::syntax::Tree;
+use crate::types::alias::Alias;
+use crate::types::Type;
 
 use std::collections::btree_map::Entry;
 
@@ -15,10 +21,98 @@ use crate::syntax::Argument;
 #[derive(Debug)]
 pub struct Parser<'i> {
     input: &'i str,
+    file: String,
     index: usize,
-    vars: BTreeMap<String, usize>,
+    // Maps names to the id and span of their first, still unmatched usage.
+    vars: BTreeMap<String, (usize, Span)>,
+    // Source names of the vars of the definition being parsed.
+    names: BTreeMap<usize, String>,
     new_var: usize,
+    // Names used so far in the definition, and whether each use was of a name that the left
+    // operand of an enclosing `&` uses, which are in `shared` until the right operand uses them.
+    used: Vec<(String, bool)>,
+    shared: BTreeSet<String>,
+    // Propositional variables of the formulas in the current signature, by name.
+    type_vars: BTreeMap<String, usize>,
+    // Names of the propositional variables, and which of them are bound by a quantifier.
+    type_names: BTreeMap<usize, String>,
+    bound_type_vars: BTreeSet<usize>,
+    // Named formulas declared so far, which the formulas after them can use.
+    aliases: BTreeMap<String, Alias>,
+    // How the source is written, for the formatter.
+    layout: Layout,
 }
+
+/// How a source is written, which the formatter prints it from: the tokens that the parser
+/// consumed, the comments it skipped, and where each item and instruction is.
+#[derive(Debug, Default)]
+pub(crate) struct Layout {
+    pub(crate) tokens: Vec<Token>,
+    /// The end of each comment by its start, without the line break.
+    pub(crate) comments: BTreeMap<usize, usize>,
+    /// The items of the book, each after the instructions of its body if it's a definition.
+    pub(crate) elements: Vec<Element>,
+}
+
+/// A token, or a mark between tokens, which is empty.
+#[derive(Debug, Clone)]
+pub(crate) struct Token {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) kind: TokenKind,
+}
+
+/// What a token is, which decides how it's spaced from the tokens around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    /// A name, a path, a hole or a constant.
+    Word,
+    /// The `(` or `[` of the arguments of an agent, the ports of a definition or the
+    /// parameters of an alias.
+    Open,
+    /// A `(` that groups a tree or a formula.
+    Paren,
+    Close,
+    /// `=`, a binary operator or a connective.
+    Infix,
+    /// `!`, `?`, `+` or `~` in front of their operand.
+    Prefix,
+    /// `+` or `'` after their operand.
+    Postfix,
+    /// `∀`, `∃`, `@` or `$`.
+    Binder,
+    /// The variable of a binder.
+    BinderVar,
+    /// The `.` after the variable of a binder.
+    Dot,
+    /// The `:` in front of the type of a port.
+    Colon,
+    /// A mark between the parameters or the arguments of an alias, with or without a comma.
+    Comma,
+    /// A mark between the ports of a definition, with or without a comma.
+    Separator,
+    /// A mark in front of each tree of an argument.
+    Item,
+    /// The `{` or `}` of a definition.
+    Brace,
+}
+
+/// An item of a book, or an instruction of a definition, by the span of its tokens.
+#[derive(Debug, Clone)]
+pub(crate) struct Element {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) kind: ElementKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ElementKind {
+    Import,
+    Alias,
+    Definition,
+    Instruction,
+}
+
 impl<'i> TSPLParser<'i> for Parser<'i> {
     fn input(&mut self) -> &'i str {
         &self.input
@@ -26,123 +120,955 @@ impl<'i> TSPLParser<'i> for Parser<'i> {
     fn index(&mut self) -> &mut usize {
         &mut self.index
     }
+    // Like the default, but the comments are kept in the layout.
+    fn skip_trivia(&mut self) {
+        while let Some(c) = self.peek_one() {
+            if c.is_ascii_whitespace() {
+                self.advance_one();
+            } else if self.input[self.index..].starts_with("//") {
+                let start = self.index;
+                let line = match self.input[start..].find('\n') {
+                    Some(length) => &self.input[start..start + length],
+                    None => &self.input[start..],
+                };
+                self.layout
+                    .comments
+                    .insert(start, start + line.trim_end().len());
+                self.index += line.len();
+            } else {
+                break;
+            }
+        }
+    }
 }
 impl<'i> Parser<'i> {
-    pub fn new(input: &'i str) -> Self {
+    pub fn new(input: &'i str, file: &str) -> Self {
         Self {
             input,
+            file: file.to_string(),
             index: 0,
             vars: BTreeMap::new(),
+            names: BTreeMap::new(),
             new_var: 0,
+            used: vec![],
+            shared: BTreeSet::new(),
+            type_vars: BTreeMap::new(),
+            type_names: BTreeMap::new(),
+            bound_type_vars: BTreeSet::new(),
+            aliases: BTreeMap::new(),
+            layout: Layout::default(),
         }
     }
-    pub fn parse_instr(&mut self) -> Result<Instruction, String> {
+    /// How the source parsed so far is written.
+    pub(crate) fn into_layout(self) -> Layout {
+        self.layout
+    }
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.index, &self.file)
+    }
+    /// Records the token from `start` to here.
+    fn token(&mut self, start: usize, kind: TokenKind) {
+        self.layout.tokens.push(Token {
+            start,
+            end: self.index,
+            kind,
+        });
+    }
+    /// Records a mark here.
+    fn mark(&mut self, kind: TokenKind) {
+        self.token(self.index, kind);
+    }
+    /// Records the element from `start` to `end`.
+    fn element(&mut self, start: usize, end: usize, kind: ElementKind) {
+        self.layout.elements.push(Element { start, end, kind });
+    }
+    fn error_here<T>(&mut self, message: impl Into<String>) -> Result<T, Diagnostic> {
+        self.skip_trivia();
+        let end = (self.index + 1).min(self.input.len());
+        Err(Diagnostic::new(
+            message,
+            Span::new(self.index, end, &self.file),
+        ))
+    }
+    fn expect(&mut self, text: &str) -> Result<(), Diagnostic> {
+        match self.consume(text) {
+            Ok(()) => Ok(()),
+            Err(_) => self.error_here(format!("Expected `{}`", text)),
+        }
+    }
+    /// Like `expect`, recording the token.
+    fn expect_token(&mut self, text: &str, kind: TokenKind) -> Result<(), Diagnostic> {
+        self.expect(text)?;
+        self.token(self.index - text.len(), kind);
+        Ok(())
+    }
+    fn expect_name(&mut self) -> Result<String, Diagnostic> {
+        match self.parse_name() {
+            Ok(name) => Ok(name),
+            Err(_) => self.error_here("Expected a name"),
+        }
+    }
+    pub fn parse_instr(&mut self) -> Result<Instruction, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
         let a = self.parse_tree()?;
+        let end = self.index;
         self.skip_trivia();
         if self.peek_one() == Some('=') {
-            self.consume("=")?;
+            self.expect_token("=", TokenKind::Infix)?;
             let b = self.parse_tree()?;
+            let span = self.span_from(start);
+            self.element(start, self.index, ElementKind::Instruction);
             self.skip_trivia();
-            Ok(Instruction::Monocut(a, b))
+            Ok(Instruction::Monocut(a, b, span))
         } else {
-            let Tree::Agent(name, args) = a else {
-                return Err("Found standalone var".to_string());
+            self.element(start, end, ElementKind::Instruction);
+            let span = self.span_from(start);
+            let Tree::Agent(name, mut args, _) = a else {
+                return Err(Diagnostic::new("Found standalone var", span));
             };
+            // A multicut's wires are always in separate partitions, so `Foo(a b)` is `Foo(a)(b)`.
+            if let [Argument::Partition(p, _)] = &args[..]
+                && p.len() > 1
+            {
+                let Some(Argument::Partition(p, _)) = args.pop() else {
+                    unreachable!()
+                };
+                return Ok(Instruction::Multicut(name, p, span));
+            }
             let mut new_args = vec![];
             for i in args {
-                let Argument::Partition(p) = i else {
-                    return Err("Multicut requires partitions, not boxes".to_string());
+                let Argument::Partition(p, arg_span) = i else {
+                    return Err(Diagnostic::new(
+                        "Multicut requires partitions, not boxes",
+                        i.span().clone(),
+                    ));
                 };
                 let Ok([p]): Result<[Tree; 1], _> = p.try_into() else {
-                    return Err("Too many wires in multicut partition".to_string());
+                    return Err(Diagnostic::new(
+                        "Multicut partitions must contain exactly one wire",
+                        arg_span,
+                    ));
                 };
                 new_args.push(p);
             }
-            Ok(Instruction::Multicut(name, new_args))
+            Ok(Instruction::Multicut(name, new_args, span))
         }
     }
-    pub fn parse_book(&mut self) -> Result<super::Book, String> {
-        let mut v = vec![];
+    pub fn parse_book(&mut self) -> Result<super::Book, Diagnostic> {
+        let mut book = super::Book::default();
+        self.skip_trivia();
         while !self.is_eof() {
-            v.push(self.parse_net()?);
+            if self.peek_keyword("import") {
+                book.imports.push(self.parse_import()?);
+            } else if self.peek_keyword("type") {
+                book.aliases.push(self.parse_alias()?);
+            } else {
+                book.nets.push(self.parse_net()?);
+            }
             self.skip_trivia();
         }
-        Ok(v)
+        Ok(book)
+    }
+    fn peek_keyword(&mut self, keyword: &str) -> bool {
+        self.peek_many(keyword.len()) == Some(keyword)
+            && self.input[self.index + keyword.len()..]
+                .chars()
+                .next()
+                .is_some_and(|x| x.is_whitespace() || x == '"')
     }
-    pub fn parse_net(&mut self) -> Result<AstNet, String> {
-        let Tree::Agent(name, args) = self.parse_tree()? else {
-            return Err("Not a good net name!".to_string());
+    /// Parses `import "path.line"` or `import "path.line" as name`.
+    pub fn parse_import(&mut self) -> Result<super::Import, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        self.expect_token("import", TokenKind::Word)?;
+        self.skip_trivia();
+        let path_start = self.index;
+        let path = match self.parse_quoted_string() {
+            Ok(path) => path,
+            Err(_) => return self.error_here("Expected a quoted path"),
+        };
+        self.token(path_start, TokenKind::Word);
+        let index = self.index;
+        self.skip_trivia();
+        let namespace = if self.peek_keyword("as") {
+            self.expect_token("as", TokenKind::Word)?;
+            self.skip_trivia();
+            let name_start = self.index;
+            let name = self.expect_name()?;
+            self.token(name_start, TokenKind::Word);
+            name
+        } else {
+            self.index = index;
+            let stem = std::path::Path::new(&path)
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .unwrap_or("");
+            if stem.is_empty() || !stem.chars().all(|x| x.is_alphanumeric() || x == '_') {
+                return Err(Diagnostic::new(
+                    format!("`{}` can't be used as a namespace, add `as name`", stem),
+                    self.span_from(start),
+                ));
+            }
+            stem.to_string()
         };
+        self.element(start, self.index, ElementKind::Import);
+        Ok(super::Import {
+            path,
+            namespace,
+            span: self.span_from(start),
+        })
+    }
+    /// Parses `type Name = formula`, or `type Name(A, B) = formula` with parameters.
+    pub fn parse_alias(&mut self) -> Result<Alias, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        self.expect_token("type", TokenKind::Word)?;
+        self.skip_trivia();
+        let name_start = self.index;
+        let name = self.expect_formula_name()?;
+        self.token(name_start, TokenKind::Word);
+        if self.aliases.contains_key(&name) {
+            return Err(Diagnostic::new(
+                format!("`{}` is declared more than once", name),
+                self.span_from(name_start),
+            ));
+        }
+        self.type_vars.clear();
+        self.type_names.clear();
+        self.bound_type_vars.clear();
+        let mut params = vec![];
+        if self.peek_one() == Some('(') {
+            self.advance_one();
+            self.token(self.index - 1, TokenKind::Open);
+            loop {
+                self.skip_trivia();
+                match self.peek_one() {
+                    Some(')') => break,
+                    Some(',') => {
+                        self.advance_one();
+                    }
+                    _ => {
+                        if !params.is_empty() {
+                            self.mark(TokenKind::Comma);
+                        }
+                        let param_start = self.index;
+                        let param = self.expect_formula_name()?;
+                        self.token(param_start, TokenKind::Word);
+                        if self.type_vars.contains_key(&param) {
+                            return Err(Diagnostic::new(
+                                format!("`{}` is a parameter more than once", param),
+                                self.span_from(param_start),
+                            ));
+                        }
+                        let id = self.type_names.len();
+                        self.type_names.insert(id, param.clone());
+                        self.type_vars.insert(param, id);
+                        params.push(id);
+                    }
+                }
+            }
+            self.expect_token(")", TokenKind::Close)?;
+        }
+        self.skip_trivia();
+        self.expect_token("=", TokenKind::Infix)?;
+        self.skip_trivia();
+        let body_start = self.index;
+        let body = self.parse_formula()?;
+        // Variables that aren't parameters would have no meaning where the alias is used.
+        if let Some((var, _)) = self.type_vars.iter().find(|(_, id)| !params.contains(id)) {
+            return Err(Diagnostic::new(
+                format!("`{}` is not a parameter of `{}`", var, name),
+                self.span_from(body_start),
+            ));
+        }
+        self.element(start, self.index, ElementKind::Alias);
+        let alias = Alias {
+            name,
+            params,
+            body,
+            names: core::mem::take(&mut self.type_names),
+            span: self.span_from(start),
+        };
+        self.aliases.insert(alias.name.clone(), alias.clone());
+        Ok(alias)
+    }
+    /// Parses the arguments of `alias`, as in `Pair(A, B)`, and expands it.
+    fn parse_alias_use(&mut self, alias: &Alias, start: usize) -> Result<Type, Diagnostic> {
+        let mut args = vec![];
+        if self.peek_one() == Some('(') {
+            self.advance_one();
+            self.token(self.index - 1, TokenKind::Open);
+            loop {
+                self.skip_trivia();
+                match self.peek_one() {
+                    Some(')') => break,
+                    Some(',') => {
+                        self.advance_one();
+                    }
+                    _ => {
+                        if !args.is_empty() {
+                            self.mark(TokenKind::Comma);
+                        }
+                        args.push(self.parse_formula()?)
+                    }
+                }
+            }
+            self.expect_token(")", TokenKind::Close)?;
+        }
+        if args.len() != alias.params.len() {
+            return Err(Diagnostic::new(
+                format!(
+                    "`{}` takes {} arguments, but {} were given",
+                    alias.name,
+                    alias.params.len(),
+                    args.len()
+                ),
+                self.span_from(start),
+            ));
+        }
+        Ok(alias.expand(&args, |name| {
+            let id = self.type_names.len();
+            self.type_names.insert(id, name.to_string());
+            self.bound_type_vars.insert(id);
+            id
+        }))
+    }
+    /// Parses `Name(a b c)`. Ports may be followed by their type, as in `Name(a: A', b: A)`.
+    fn parse_header(&mut self) -> Result<(String, Span, Vec<Argument>, Signature), Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        let name = self.expect_name()?;
+        let span = self.span_from(start);
+        self.token(start, TokenKind::Word);
+        self.skip_trivia();
+        let ports_start = self.index;
+        self.expect_token("(", TokenKind::Open)?;
+        let mut ports = vec![];
+        let mut types = vec![];
+        self.type_vars.clear();
+        self.bound_type_vars.clear();
+        loop {
+            self.skip_trivia();
+            match self.peek_one() {
+                Some(')') => break,
+                Some(',') => {
+                    self.advance_one();
+                }
+                Some(x) if x.is_ascii_lowercase() => {
+                    if !ports.is_empty() {
+                        self.mark(TokenKind::Separator);
+                    }
+                    let port_start = self.index;
+                    let id = self.parse_var()?;
+                    ports.push(Tree::Var(id, self.span_from(port_start)));
+                    self.token(port_start, TokenKind::Word);
+                    if self.eat_token(":", TokenKind::Colon) {
+                        self.skip_trivia();
+                        let type_start = self.index;
+                        let t = self.parse_formula()?;
+                        types.push(Some((t, self.span_from(type_start))));
+                    } else {
+                        types.push(None);
+                    }
+                }
+                _ => return self.error_here("Expected a port name"),
+            }
+        }
+        self.expect_token(")", TokenKind::Close)?;
+        let ports = vec![Argument::Partition(ports, self.span_from(ports_start))];
+        Ok((name, span, ports, types))
+    }
+    pub fn parse_net(&mut self) -> Result<AstNet, Diagnostic> {
+        let (name, span, args, port_types) = self.parse_header()?;
         self.skip_trivia();
         let mut instr = vec![];
-        self.consume("{")?;
+        self.expect_token("{", TokenKind::Brace)?;
         while !matches!(self.peek_one(), Some('}')) {
+            if self.is_eof() {
+                return self.error_here(format!("Unclosed definition of `{}`", name));
+            }
             instr.push(self.parse_instr()?);
         }
-        self.consume("}")?;
-        let mut desugar = Desugarer::new(self.new_var);
+        self.expect_token("}", TokenKind::Brace)?;
+        self.element(span.start, self.index, ElementKind::Definition);
+        // Every variable must be used exactly twice within a definition.
+        if let Some((var, (_, span))) = core::mem::take(&mut self.vars).into_iter().next() {
+            return Err(Diagnostic::new(
+                format!("Variable `{}` is only used once", var),
+                span,
+            ));
+        }
+        self.used.clear();
+        let mut desugar = Desugarer::new(self.new_var, core::mem::take(&mut self.names));
         for i in instr {
-            desugar.desugar_instr(i);
+            desugar.desugar_instr(i)?;
         }
         let args = desugar.desugar_contents(args);
         Ok(AstNet {
             name,
             outputs: args,
             instructions: desugar.output,
+            names: desugar.names,
+            port_types,
+            type_names: core::mem::take(&mut self.type_names),
+            span,
         })
     }
-    pub fn parse_tree(&mut self) -> Result<Tree, String> {
+    /// Parses a term, which may use the operators from `future-syntax.md`.
+    /// Operators are lowered into the agent forms they stand for.
+    ///
+    /// Precedence, from strongest to weakest: `*`, `|`, `&`, `+`, `!`, `?`, `@` and `$`.
+    /// Binary operators associate to the right.
+    pub fn parse_tree(&mut self) -> Result<Tree, Diagnostic> {
+        self.parse_binder()
+    }
+    /// Consumes `op` if it is the next non-trivia character.
+    /// Otherwise, the trivia is left in place, so that postfix operators can check for adjacency.
+    fn eat(&mut self, op: char) -> bool {
+        let index = self.index;
         self.skip_trivia();
-        if self.peek_one().is_some_and(|x| x.is_ascii_lowercase()) {
-            Ok(Tree::Var(self.parse_var()?))
+        if self.peek_one() == Some(op) {
+            self.advance_one();
+            true
         } else {
-            let name = self.parse_name()?;
-            self.skip_trivia();
-            let mut v = vec![];
-            while matches!(self.peek_one(), Some('(') | Some('[')) {
-                v.push(self.parse_argument()?);
+            self.index = index;
+            false
+        }
+    }
+    /// Whether an argument of the agent that was just parsed comes next. Arguments must start
+    /// on the same line, so that `(a b) = c` on a new line doesn't continue a multicut.
+    fn peek_argument(&mut self) -> bool {
+        let index = self.index;
+        self.skip_trivia();
+        let found = matches!(self.peek_one(), Some('(') | Some('['))
+            && !self.input[index..self.index].contains('\n');
+        if !found {
+            self.index = index;
+        }
+        found
+    }
+    /// Like `eat`, but the operator must be on the same line as its left operand.
+    /// This keeps `?a = b` on a new line from continuing the previous instruction.
+    fn eat_infix(&mut self, op: char) -> bool {
+        let index = self.index;
+        if self.eat(op) && !self.input[index..self.index].contains('\n') {
+            self.token(self.index - op.len_utf8(), TokenKind::Infix);
+            true
+        } else {
+            self.index = index;
+            false
+        }
+    }
+    // `@x A` and `$x A` are `All[x A]` and `Any[x A]`, whose context the compiler fills in.
+    fn parse_binder(&mut self) -> Result<Tree, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        let symbol = match self.peek_one() {
+            Some('@') => "All",
+            Some('$') => "Any",
+            _ => return self.parse_contraction(),
+        };
+        self.advance_one();
+        self.token(start, TokenKind::Binder);
+        self.skip_trivia();
+        let var_start = self.index;
+        if !self.peek_one().is_some_and(|x| x.is_ascii_lowercase()) {
+            return self.error_here("Expected a variable after the quantifier");
+        }
+        let var = Tree::Var(self.parse_var()?, self.span_from(var_start));
+        self.token(var_start, TokenKind::BinderVar);
+        let body = self.parse_binder()?;
+        Ok(agent(
+            symbol,
+            vec![boxed(vec![var, body])],
+            &self.span_from(start),
+        ))
+    }
+    // `?a` is a dereliction, and `a ? b` a contraction.
+    fn parse_contraction(&mut self) -> Result<Tree, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        let left = self.parse_dereliction()?;
+        if self.eat_infix('?') {
+            let right = self.parse_contraction()?;
+            let span = self.span_from(start);
+            Ok(agent("Cntr", vec![partition(vec![left, right])], &span))
+        } else {
+            Ok(left)
+        }
+    }
+    fn parse_dereliction(&mut self) -> Result<Tree, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        if self.eat_token("?", TokenKind::Prefix) {
+            let inner = self.parse_dereliction()?;
+            Ok(agent(
+                "Dere",
+                vec![partition(vec![inner])],
+                &self.span_from(start),
+            ))
+        } else {
+            self.parse_promotion()
+        }
+    }
+    // `!a` is `Exp1[a]`, whose context the compiler fills in.
+    fn parse_promotion(&mut self) -> Result<Tree, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        if self.eat_token("!", TokenKind::Prefix) {
+            let inner = self.parse_promotion()?;
+            Ok(agent(
+                "Exp1",
+                vec![boxed(vec![inner])],
+                &self.span_from(start),
+            ))
+        } else {
+            self.parse_plus()
+        }
+    }
+    // `+a` is `Right(a)`, and `a+` is `Left(a)`. The postfix `+` must follow its operand directly.
+    fn parse_plus(&mut self) -> Result<Tree, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        if self.eat_token("+", TokenKind::Prefix) {
+            let inner = self.parse_plus()?;
+            return Ok(agent(
+                "Right",
+                vec![partition(vec![inner])],
+                &self.span_from(start),
+            ));
+        }
+        let mut tree = self.parse_with()?;
+        while self.peek_one() == Some('+') {
+            self.advance_one();
+            self.token(self.index - 1, TokenKind::Postfix);
+            tree = agent("Left", vec![partition(vec![tree])], &self.span_from(start));
+        }
+        Ok(tree)
+    }
+    // `a & b` is `With[a][b]`, whose context the compiler fills in.
+    fn parse_with(&mut self) -> Result<Tree, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        let used = self.used.len();
+        let left = self.parse_par()?;
+        if self.eat_infix('&') {
+            // The names that `left` uses once, which are wires from outside of it.
+            let mut counts: BTreeMap<&str, (usize, bool)> = BTreeMap::new();
+            for (name, shared) in &self.used[used..] {
+                let count = counts.entry(name).or_default();
+                count.0 += 1;
+                count.1 |= shared;
             }
-            Ok(Tree::Agent(name, v))
+            let outside: BTreeSet<String> = counts
+                .into_iter()
+                .filter(|(_, (count, shared))| *shared || count % 2 == 1)
+                .map(|(name, _)| name.to_string())
+                .collect();
+            self.shared.extend(outside.iter().cloned());
+            let right = self.parse_with()?;
+            // A name that `right` doesn't use is reported by the compiler.
+            self.shared.retain(|x| !outside.contains(x));
+            Ok(agent(
+                "With",
+                vec![boxed(vec![left]), boxed(vec![right])],
+                &self.span_from(start),
+            ))
+        } else {
+            Ok(left)
         }
     }
-    pub fn parse_var(&mut self) -> Result<usize, String> {
+    fn parse_par(&mut self) -> Result<Tree, Diagnostic> {
         self.skip_trivia();
-        let name = self.parse_name()?;
+        let start = self.index;
+        let left = self.parse_times()?;
+        if self.eat_infix('|') {
+            let right = self.parse_par()?;
+            Ok(agent(
+                "Par",
+                vec![partition(vec![left, right])],
+                &self.span_from(start),
+            ))
+        } else {
+            Ok(left)
+        }
+    }
+    fn parse_times(&mut self) -> Result<Tree, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        let left = self.parse_atom()?;
+        if self.eat_infix('*') {
+            let right = self.parse_times()?;
+            Ok(agent(
+                "Times",
+                vec![partition(vec![left]), partition(vec![right])],
+                &self.span_from(start),
+            ))
+        } else {
+            Ok(left)
+        }
+    }
+    fn parse_atom(&mut self) -> Result<Tree, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        match self.peek_one() {
+            Some('(') => {
+                match self.peek_many(3) {
+                    Some("(*)") => {
+                        self.advance_many(3);
+                        self.token(start, TokenKind::Word);
+                        return Ok(agent("One", vec![], &self.span_from(start)));
+                    }
+                    Some("(&)") => {
+                        self.advance_many(3);
+                        self.token(start, TokenKind::Word);
+                        let span = self.span_from(start);
+                        let one = agent("One", vec![], &span);
+                        return Ok(agent("True", vec![partition(vec![one])], &span));
+                    }
+                    Some("(|)") | Some("(?)") => {
+                        self.advance_many(3);
+                        return Err(Diagnostic::new(
+                            "This constant needs a context. Write it as `False(..)[..]` or `Weak(..)[..]`",
+                            self.span_from(start),
+                        ));
+                    }
+                    _ => (),
+                }
+                self.expect_token("(", TokenKind::Paren)?;
+                let tree = self.parse_tree()?;
+                self.expect_token(")", TokenKind::Close)?;
+                Ok(tree)
+            }
+            // A hole, as in `_` or `_name`, is an agent without arguments.
+            Some('_') => {
+                self.advance_one();
+                let name = format!("_{}", self.take_while(|x| x.is_alphanumeric() || x == '_'));
+                self.token(start, TokenKind::Word);
+                Ok(agent(&name, vec![], &self.span_from(start)))
+            }
+            // A prefix operator in operand position extends as far right as possible.
+            Some('!') | Some('?') | Some('+') | Some('@') | Some('$') => self.parse_binder(),
+            Some(x) if x.is_ascii_lowercase() && !self.peek_qualified_name() => {
+                let id = self.parse_var()?;
+                self.token(start, TokenKind::Word);
+                Ok(Tree::Var(id, self.span_from(start)))
+            }
+            _ => {
+                let mut name = self.expect_name()?;
+                // Definitions from imported files are qualified, as in `lib::Name`.
+                while self.peek_many(2) == Some("::") {
+                    self.advance_many(2);
+                    name = format!("{}::{}", name, self.expect_name()?);
+                }
+                let span = self.span_from(start);
+                self.token(start, TokenKind::Word);
+                let mut v = vec![];
+                while self.peek_argument() {
+                    v.push(self.parse_argument()?);
+                }
+                Ok(Tree::Agent(name, v, span))
+            }
+        }
+    }
+    /// Parses a linear logic formula, written as `Type::show` prints it or in the ASCII notation
+    /// of `future-syntax.md`.
+    ///
+    /// Precedence, from strongest to weakest: `'` (dual), the prefix `!`, `?`, `~`, `∀x.` and `∃x.`,
+    /// then `⊗`, `⅋`, `&`, `⊕` and `⊸`. Binary connectives associate to the right,
+    /// and the body of a quantifier extends as far right as possible.
+    pub fn parse_formula(&mut self) -> Result<Type, Diagnostic> {
+        let left = self.parse_formula_binary(0)?;
+        if self.eat_token("⊸", TokenKind::Infix) || self.eat_token("-o", TokenKind::Infix) {
+            let right = self.parse_formula()?;
+            Ok(Type::Par(Box::new(!left), Box::new(right)))
+        } else {
+            Ok(left)
+        }
+    }
+    // Binary connectives from weakest to strongest, and their ASCII spelling.
+    const CONNECTIVES: [(char, char); 4] = [('⊕', '+'), ('&', '&'), ('⅋', '|'), ('⊗', '*')];
+    fn parse_formula_binary(&mut self, level: usize) -> Result<Type, Diagnostic> {
+        let Some((op, ascii)) = Self::CONNECTIVES.get(level) else {
+            return self.parse_formula_unary();
+        };
+        let left = self.parse_formula_binary(level + 1)?;
+        if !self.eat_token(&op.to_string(), TokenKind::Infix)
+            && !self.eat_token(&ascii.to_string(), TokenKind::Infix)
+        {
+            return Ok(left);
+        }
+        let right = Box::new(self.parse_formula_binary(level)?);
+        let left = Box::new(left);
+        Ok(match op {
+            '⊕' => Type::Plus(left, right),
+            '&' => Type::With(left, right),
+            '⅋' => Type::Par(left, right),
+            _ => Type::Times(left, right),
+        })
+    }
+    fn parse_formula_unary(&mut self) -> Result<Type, Diagnostic> {
+        self.skip_trivia();
+        match self.peek_one() {
+            Some(op @ ('!' | '?' | '~')) => {
+                self.advance_one();
+                self.token(self.index - 1, TokenKind::Prefix);
+                let t = self.parse_formula_unary()?;
+                Ok(match op {
+                    '!' => Type::Ofc(Box::new(t)),
+                    '?' => Type::Why(Box::new(t)),
+                    _ => !t,
+                })
+            }
+            Some(q @ ('∀' | '∃' | '@' | '$')) => {
+                self.advance_one();
+                self.token(self.index - q.len_utf8(), TokenKind::Binder);
+                self.skip_trivia();
+                let name_start = self.index;
+                let name = self.expect_formula_name()?;
+                self.token(name_start, TokenKind::BinderVar);
+                // The dot is optional in the ASCII notation, as in `@x x | ~x`.
+                if matches!(q, '∀' | '∃') {
+                    self.expect_token(".", TokenKind::Dot)?;
+                } else {
+                    self.eat_token(".", TokenKind::Dot);
+                }
+                let id = self.type_names.len();
+                self.type_names.insert(id, name.clone());
+                self.bound_type_vars.insert(id);
+                let shadowed = self.type_vars.insert(name.clone(), id);
+                let body = self.parse_formula();
+                match shadowed {
+                    Some(shadowed) => self.type_vars.insert(name, shadowed),
+                    None => self.type_vars.remove(&name),
+                };
+                let body = Box::new(body?);
+                Ok(if matches!(q, '∀' | '@') {
+                    Type::All(id, body)
+                } else {
+                    Type::Any(id, body)
+                })
+            }
+            _ => {
+                let mut t = self.parse_formula_atom()?;
+                while self.peek_one() == Some('\'') {
+                    self.advance_one();
+                    self.token(self.index - 1, TokenKind::Postfix);
+                    t = !t;
+                }
+                Ok(t)
+            }
+        }
+    }
+    // Unlike other names, the names of propositional variables can't contain `.`,
+    // which ends the variable of a quantifier.
+    fn expect_formula_name(&mut self) -> Result<String, Diagnostic> {
+        let name = self.take_while(|x| x.is_alphanumeric() || x == '_');
+        if name.is_empty() {
+            return self.error_here("Expected a name");
+        }
+        Ok(name.to_string())
+    }
+    fn parse_formula_atom(&mut self) -> Result<Type, Diagnostic> {
+        self.skip_trivia();
+        // Units in the ASCII notation are connectives in parentheses.
+        for (unit, t) in [
+            ("(*)", Type::One),
+            ("(|)", Type::False),
+            ("(+)", Type::Zero),
+            ("(&)", Type::True),
+        ] {
+            if self.eat_token(unit, TokenKind::Word) {
+                return Ok(t);
+            }
+        }
+        let start = self.index;
+        let constant = match self.peek_one() {
+            Some('(') => {
+                self.advance_one();
+                self.token(start, TokenKind::Paren);
+                let t = self.parse_formula()?;
+                self.expect_token(")", TokenKind::Close)?;
+                return Ok(t);
+            }
+            Some('1') => Type::One,
+            Some('⊥') => Type::False,
+            Some('0') => Type::Zero,
+            Some('⊤') => Type::True,
+            Some('_') => Type::Hole,
+            Some(x) if x.is_alphabetic() => {
+                let name = self.expect_formula_name()?;
+                self.token(start, TokenKind::Word);
+                // Variables shadow aliases.
+                if !self.type_vars.contains_key(&name)
+                    && let Some(alias) = self.aliases.get(&name).cloned()
+                {
+                    return self.parse_alias_use(&alias, start);
+                }
+                let id = match self.type_vars.get(&name) {
+                    Some(id) => *id,
+                    None => {
+                        let id = self.type_names.len();
+                        self.type_names.insert(id, name.clone());
+                        self.type_vars.insert(name, id);
+                        id
+                    }
+                };
+                // Variables bound by a quantifier can't be unified, whichever the quantifier,
+                // so that dualizing `∀x.x` gives the same formula as parsing `∃x.x'`.
+                return Ok(if self.bound_type_vars.contains(&id) {
+                    Type::Eigenvar(id, false)
+                } else {
+                    Type::Var(id, false)
+                });
+            }
+            _ => return self.error_here("Expected a formula"),
+        };
+        self.advance_one();
+        self.token(start, TokenKind::Word);
+        Ok(constant)
+    }
+    /// Like `eat`, for a multi-character token.
+    fn eat_str(&mut self, token: &str) -> bool {
+        let index = self.index;
+        self.skip_trivia();
+        if self.input[self.index..].starts_with(token) {
+            self.index += token.len();
+            true
+        } else {
+            self.index = index;
+            false
+        }
+    }
+    /// Like `eat_str`, recording the token.
+    fn eat_token(&mut self, token: &str, kind: TokenKind) -> bool {
+        let eaten = self.eat_str(token);
+        if eaten {
+            self.token(self.index - token.len(), kind);
+        }
+        eaten
+    }
+    /// Whether the next name is followed by `::`, which makes it a namespace and not a variable.
+    fn peek_qualified_name(&mut self) -> bool {
+        let index = self.index;
+        let _ = self.parse_name();
+        let qualified = self.peek_many(2) == Some("::");
+        self.index = index;
+        qualified
+    }
+    pub fn parse_var(&mut self) -> Result<usize, Diagnostic> {
+        self.skip_trivia();
+        let start = self.index;
+        let name = self.expect_name()?;
+        let span = self.span_from(start);
+        // Both sides of a `&` use the same wires from outside, which the compiler matches by
+        // name. So the right side's use of one doesn't pair with the left side's.
+        if self.shared.remove(&name) {
+            self.used.push((name.clone(), true));
+            self.names.insert(self.new_var, name);
+            self.new_var += 1;
+            return Ok(self.new_var - 1);
+        }
+        self.used.push((name.clone(), false));
         match self.vars.entry(name) {
-            Entry::Occupied(e) => Ok(e.remove()),
+            Entry::Occupied(e) => Ok(e.remove().0),
             Entry::Vacant(e) => {
                 let id = self.new_var;
-                e.insert(id);
+                self.names.insert(id, e.key().clone());
+                e.insert((id, span));
                 self.new_var += 1;
                 Ok(id)
             }
         }
     }
-    pub fn parse_argument(&mut self) -> Result<Argument, String> {
+    pub fn parse_argument(&mut self) -> Result<Argument, Diagnostic> {
         self.skip_trivia();
+        let start = self.index;
         match self.peek_one() {
             Some('(') => {
-                self.consume("(")?;
+                self.expect_token("(", TokenKind::Open)?;
                 let mut v = vec![];
                 while self.peek_one() != Some(')') {
+                    if self.is_eof() {
+                        return self.error_here("Expected `)`");
+                    }
+                    self.mark(TokenKind::Item);
                     v.push(self.parse_tree()?);
+                    self.skip_trivia();
                 }
-                let _ = self.consume(")");
-                Ok(Argument::Partition(v))
+                self.expect_token(")", TokenKind::Close)?;
+                Ok(Argument::Partition(v, self.span_from(start)))
             }
             Some('[') => {
-                self.consume("[")?;
+                self.expect_token("[", TokenKind::Open)?;
                 let mut v = vec![];
                 while self.peek_one() != Some(']') {
+                    if self.is_eof() {
+                        return self.error_here("Expected `]`");
+                    }
+                    self.mark(TokenKind::Item);
                     v.push(self.parse_tree()?);
+                    self.skip_trivia();
                 }
-                let _ = self.consume("]");
-                Ok(Argument::Box(v))
+                self.expect_token("]", TokenKind::Close)?;
+                Ok(Argument::Box(v, self.span_from(start)))
             }
-            _ => Err("Not an argument!".to_string()),
+            _ => self.error_here("Not an argument!"),
         }
     }
 }
-pub fn parse_file(s: &str) -> Result<crate::syntax::Book, String> {
-    Parser::new(s).parse_book()
+fn agent(name: &str, args: Vec<Argument>, span: &Span) -> Tree {
+    Tree::Agent(name.to_string(), args, span.clone())
+}
+// Arguments synthesized by operators are spanned by the tree they belong to.
+fn partition(trees: Vec<Tree>) -> Argument {
+    let span = trees.first().map(|x| x.span().clone()).unwrap_or_default();
+    Argument::Partition(trees, span)
+}
+fn boxed(trees: Vec<Tree>) -> Argument {
+    let span = trees.first().map(|x| x.span().clone()).unwrap_or_default();
+    Argument::Box(trees, span)
+}
+pub fn parse_file(s: &str, file: &str) -> Result<crate::syntax::Book, Diagnostic> {
+    parse_file_with_aliases(s, file, &[])
+}
+/// Like `parse_file`, but the formulas can also use `aliases`, which were declared elsewhere.
+/// They aren't added to the book.
+pub fn parse_file_with_aliases(
+    s: &str,
+    file: &str,
+    aliases: &[Alias],
+) -> Result<crate::syntax::Book, Diagnostic> {
+    let mut parser = Parser::new(s, file);
+    for alias in aliases {
+        parser.aliases.insert(alias.name.clone(), alias.clone());
+    }
+    parser.parse_book()
+}
+/// Parses a source that consists of a single formula, along with the names of its variables.
+pub fn parse_formula(s: &str, file: &str) -> Result<(Type, BTreeMap<usize, String>), Diagnostic> {
+    let mut parser = Parser::new(s, file);
+    let t = parser.parse_formula()?;
+    if !parser.is_eof() {
+        return parser.error_here("Expected the end of the formula");
+    }
+    Ok((t, parser.type_names))
+}
+/// Parses a sequent, as in `|- A', A ⊗ B`, whose formulas share their variables.
+/// The `|-` or `⊢` in front is optional.
+pub fn parse_sequent(
+    s: &str,
+    file: &str,
+) -> Result<(Vec<Type>, BTreeMap<usize, String>), Diagnostic> {
+    let mut parser = Parser::new(s, file);
+    let _ = parser.eat_str("|-") || parser.eat_str("⊢");
+    let mut sequent = vec![parser.parse_formula()?];
+    while parser.eat(',') {
+        sequent.push(parser.parse_formula()?);
+    }
+    if !parser.is_eof() {
+        return parser.error_here("Expected `,` or the end of the sequent");
+    }
+    Ok((sequent, parser.type_names))
 }
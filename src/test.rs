@@ -1,6 +1,5 @@
 use crate::join_with;
 use glob::glob;
-use std::collections::BTreeMap;
 
 use std::path::PathBuf;
 
@@ -8,59 +7,518 @@ use std::path::PathBuf;
 fn snapshot_tests() {
     let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     d.push("tests");
-    for i in glob(&(d.to_str().unwrap().to_string() + "/**/*")).unwrap() {
+    for i in glob(&(d.to_str().unwrap().to_string() + "/**/*.line")).unwrap() {
         if let Ok(file) = i
-            && let Ok(contents) = std::fs::read_to_string(file.clone())
+            && let Ok(contents) = std::fs::read_to_string(&file)
         {
             eprintln!("{}", file.display());
-            let book = crate::syntax::parser::parse_file(&contents);
+            let file_name = file.file_name().unwrap().to_str().unwrap();
+            let mut loader = crate::syntax::module::Loader::default();
+            let book = loader.load_source(file_name, contents, file.parent().unwrap());
 
             match book {
                 Ok(book) => {
                     let mut compiler = crate::syntax::compiler::Compiler::default();
-                    compiler.compile_book(book);
-                    let mut main_net = compiler.main_net();
+                    if let Err(errors) = compiler.compile_book(book) {
+                        insta::assert_snapshot!(
+                            format!("{}/compilation", file.display()),
+                            join_with(
+                                errors.iter().map(|e| {
+                                    let e = e.to_diagnostic();
+                                    e.render(loader.source(&e.span.file))
+                                }),
+                                "\n".to_string()
+                            )
+                        );
+                        continue;
+                    }
+                    use crate::syntax::compiler::DEFAULT_ENTRY;
+                    use crate::types::show::Notation;
+                    // A book without a `Main` gets snapshots for each of its definitions.
+                    let entries: Vec<String> = if compiler.global_nets.contains_key(DEFAULT_ENTRY) {
+                        vec![DEFAULT_ENTRY.to_string()]
+                    } else {
+                        compiler.global_nets.keys().cloned().collect()
+                    };
+                    for entry in entries {
+                        let path = if entry == DEFAULT_ENTRY {
+                            file.display().to_string()
+                        } else {
+                            format!("{}/{}", file.display(), entry.replace("::", "."))
+                        };
+                        let mut main_net = compiler.entry_net(&entry).unwrap();
+                        let checked = crate::types::check(&main_net);
 
-                    // Ensure the main net is compiled correctly
-                    let mut scope = std::collections::BTreeMap::new();
-                    let show_agent = |x| format!("{:?}", x);
-                    insta::assert_snapshot!(
-                        format!("{}/compilation", file.display()),
-                        main_net.show_net(&show_agent, &mut scope, 0)
-                    );
+                        // Ensure the signature is inferred correctly
+                        let result = match &compiler.global_nets[&entry].signature {
+                            Ok(scheme) => scheme.show_with(Notation::Unicode, &compiler.aliases),
+                            Err((e, _)) => format!("error: {}", e),
+                        };
+                        insta::assert_snapshot!(format!("{}/signature", path), result);
 
-                    // Ensure the main net is normalized correctly
-                    main_net.normal(crate::net::rules::apply_rule);
-                    main_net.canonical();
-                    let mut scope = std::collections::BTreeMap::new();
-                    let show_agent = |x| format!("{:?}", x);
-                    insta::assert_snapshot!(
-                        format!("{}/normalization", file.display()),
-                        main_net.show_net(&show_agent, &mut scope, 0)
-                    );
+                        // Ensure every wire is typed correctly
+                        insta::assert_snapshot!(
+                            format!("{}/wires", path),
+                            compiler.global_nets[&entry]
+                                .wires
+                                .show_in(Notation::Unicode)
+                        );
 
-                    let mut ctx = BTreeMap::new();
+                        // Ensure each hole gets the formula it must have
+                        let holes = compiler.global_nets[&entry].show_holes(
+                            &entry,
+                            Notation::Unicode,
+                            &compiler.aliases,
+                        );
+                        if !holes.is_empty() {
+                            insta::assert_snapshot!(
+                                format!("{}/holes", path),
+                                join_with(
+                                    holes
+                                        .iter()
+                                        .map(|x| x.render_as("hole", loader.source(&x.span.file))),
+                                    "\n".to_string()
+                                )
+                            );
+                        }
 
-                    let trees = main_net.substitute_iter(main_net.ports.iter());
-                    let types = crate::types::infer(trees);
+                        // Ensure the main net is compiled correctly
+                        let mut scope = std::collections::BTreeMap::new();
+                        let show_agent = |x| format!("{:?}", x);
+                        insta::assert_snapshot!(
+                            format!("{}/compilation", path),
+                            main_net.show_net(&show_agent, &mut scope, 0)
+                        );
 
-                    let result = format!(
-                        "|- {}",
-                        join_with(
-                            types.into_iter().map(|x| x.show(&mut ctx)),
-                            ", ".to_string()
-                        )
-                    );
-                    insta::assert_snapshot!(format!("{}/typing", file.display()), result);
+                        // Ensure the main net can be written back as a definition
+                        assert_decompiles(&main_net, &path);
 
-                    let net = crate::icombs::Translator::translate_net(main_net);
+                        // Ensure the main net is normalized correctly
+                        main_net.normal(crate::net::rules::apply_rule);
+                        main_net.canonical();
+                        let mut scope = std::collections::BTreeMap::new();
+                        let show_agent = |x| format!("{:?}", x);
+                        insta::assert_snapshot!(
+                            format!("{}/normalization", path),
+                            main_net.show_net(&show_agent, &mut scope, 0)
+                        );
+                        insta::assert_snapshot!(
+                            format!("{}/decompilation", path),
+                            assert_decompiles(&main_net, &path)
+                        );
 
-                    insta::assert_snapshot!(format!("{}/translation", file.display()), net.show(),);
+                        // Typing the normal form gives a type at least as general.
+                        let trees = main_net.substitute_iter(main_net.ports.iter());
+                        let inferred = crate::types::infer_named(trees, &main_net.names);
+                        match (&checked, &inferred) {
+                            (Ok((checked, _)), Ok((inferred, _))) => {
+                                let mut subst = std::collections::BTreeMap::new();
+                                assert!(
+                                    inferred
+                                        .iter()
+                                        .zip(checked)
+                                        .all(|(i, c)| crate::types::is_instance(i, c, &mut subst)),
+                                    "the type of the normal form of {} is less general",
+                                    path
+                                );
+                            }
+                            (Ok(_), Err(_)) => {
+                                panic!("{} has a type, but its normal form doesn't", path)
+                            }
+                            (Err(e), Ok(_)) => {
+                                panic!(
+                                    "the normal form of {} has a type, but it doesn't: {}",
+                                    path, e
+                                )
+                            }
+                            (Err(_), Err(_)) => (),
+                        }
+                        let show = |result: Result<
+                            (Vec<crate::types::Type>, _),
+                            crate::types::TypeError,
+                        >| match result {
+                            Ok((types, names)) => {
+                                let mut ctx = crate::types::Type::named_scope(&types, &names);
+                                format!(
+                                    "|- {}",
+                                    join_with(
+                                        types.into_iter().map(|x| x.show_with(
+                                            &mut ctx,
+                                            Notation::Unicode,
+                                            &compiler.aliases
+                                        )),
+                                        ", ".to_string()
+                                    )
+                                )
+                            }
+                            Err(e) => format!("error: {}", e),
+                        };
+                        let result = show(inferred);
+                        insta::assert_snapshot!(format!("{}/typing", path), result);
+                        let result = show(checked);
+                        insta::assert_snapshot!(format!("{}/check", path), result);
+
+                        let net = crate::icombs::Translator::translate_net(main_net);
+                        let result = match net {
+                            Some(net) => net.show(),
+                            None => "error: the net has holes".to_string(),
+                        };
+                        insta::assert_snapshot!(format!("{}/translation", path), result);
+                    }
                 }
                 Err(e) => {
-                    insta::assert_snapshot!(format!("{}/compilation", file.display()), e);
+                    insta::assert_snapshot!(
+                        format!("{}/compilation", file.display()),
+                        e.render(loader.source(&e.span.file))
+                    );
                 }
             }
         }
     }
 }
+
+/// Decompiles `net`, and checks that compiling the definition gives `net` back.
+fn assert_decompiles(net: &crate::net::Net, path: &str) -> String {
+    let source = crate::syntax::decompiler::decompile(net, "Decompiled")
+        .unwrap_or_else(|| panic!("{} can't be decompiled", path));
+    let mut loader = crate::syntax::module::Loader::default();
+    let book = loader
+        .load_source("decompiled.line", source.clone(), std::path::Path::new(""))
+        .unwrap_or_else(|e| panic!("{}", e.render(&source)));
+    let mut compiler = crate::syntax::compiler::Compiler::default();
+    if let Err(errors) = compiler.compile_book(book) {
+        panic!(
+            "the decompilation of {} doesn't compile:\n{}",
+            path,
+            join_with(
+                errors.iter().map(|e| e.to_diagnostic().render(&source)),
+                "\n".to_string()
+            )
+        );
+    }
+    assert!(
+        alpha_equivalent(net, &compiler.global_nets["Decompiled"].net),
+        "the decompilation of {} compiles to another net:\n{}",
+        path,
+        source
+    );
+    source
+}
+
+/// Whether `a` and `b` are the same net, up to the ids of their vars and the order and
+/// orientation of their redexes.
+fn alpha_equivalent(a: &crate::net::Net, b: &crate::net::Net) -> bool {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    a.canonical();
+    b.canonical();
+    let mut vars = std::collections::BTreeMap::new();
+    a.ports.len() == b.ports.len()
+        && a.ports
+            .iter()
+            .zip(&b.ports)
+            .all(|(x, y)| same_tree(x, y, &mut vars))
+        && same_redexes(
+            &a.redexes.iter().collect::<Vec<_>>(),
+            b.redexes.iter().collect(),
+            &vars,
+        )
+}
+
+fn same_tree(
+    a: &crate::net::Tree,
+    b: &crate::net::Tree,
+    vars: &mut std::collections::BTreeMap<usize, usize>,
+) -> bool {
+    use crate::net::{PartitionOrBox, Tree};
+    match (a, b) {
+        (Tree::Var(x), Tree::Var(y)) => match vars.get(x) {
+            Some(z) => z == y,
+            None if vars.values().any(|z| z == y) => false,
+            None => {
+                vars.insert(*x, *y);
+                true
+            }
+        },
+        (Tree::Agent(s, xs), Tree::Agent(t, ys)) => {
+            s == t
+                && xs.len() == ys.len()
+                && xs.iter().zip(ys).all(|x| match x {
+                    (PartitionOrBox::Partition(p), PartitionOrBox::Partition(q)) => {
+                        p.len() == q.len() && p.iter().zip(q).all(|(x, y)| same_tree(x, y, vars))
+                    }
+                    (PartitionOrBox::Box(m), PartitionOrBox::Box(n)) => alpha_equivalent(m, n),
+                    _ => false,
+                })
+        }
+        _ => false,
+    }
+}
+
+fn same_redexes(
+    a: &[&(crate::net::Tree, crate::net::Tree)],
+    b: Vec<&(crate::net::Tree, crate::net::Tree)>,
+    vars: &std::collections::BTreeMap<usize, usize>,
+) -> bool {
+    let Some(((x, y), rest)) = a.split_first() else {
+        return b.is_empty();
+    };
+    (0..b.len()).any(|i| {
+        let (u, v) = b[i];
+        [(u, v), (v, u)].into_iter().any(|(u, v)| {
+            let mut vars = vars.clone();
+            let mut b = b.clone();
+            b.remove(i);
+            same_tree(x, u, &mut vars) && same_tree(y, v, &mut vars) && same_redexes(rest, b, &vars)
+        })
+    })
+}
+
+#[test]
+fn formula_round_trip() {
+    use crate::types::show::Notation;
+    use crate::types::Type;
+    let mut file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    file.push("tests/formulas.txt");
+    let contents = std::fs::read_to_string(&file).unwrap();
+    let mut printed = vec![];
+    for line in contents.lines() {
+        if line.trim().is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let (t, names) = Type::parse(line).unwrap_or_else(|e| panic!("{}", e.render(line)));
+        let mut shown = vec![];
+        for notation in [Notation::Unicode, Notation::Ascii] {
+            let scope = &mut Type::named_scope(std::slice::from_ref(&t), &names);
+            let s = t.show_in(scope, notation);
+            // Printing and parsing again gives back the same formula.
+            let (again, names) = Type::parse(&s).unwrap_or_else(|e| panic!("{}", e.render(&s)));
+            assert_eq!(t, again, "{} was printed as {}", line, s);
+            let scope = &mut Type::named_scope(std::slice::from_ref(&again), &names);
+            assert_eq!(s, again.show_in(scope, notation));
+            shown.push(s);
+        }
+        // Dualizing twice does nothing.
+        assert_eq!(t, !!t.clone());
+        printed.push(format!("{}\n  {}\n  {}", line, shown[0], shown[1]));
+    }
+    insta::assert_snapshot!(
+        format!("{}/printing", file.display()),
+        join_with(printed.into_iter(), "\n".to_string())
+    );
+}
+
+#[test]
+fn proof_search() {
+    use crate::types::prove::Prover;
+    use crate::types::show::Notation;
+    use crate::types::Type;
+    let mut file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    file.push("tests/proofs.txt");
+    let contents = std::fs::read_to_string(&file).unwrap();
+    let mut printed = vec![];
+    for line in contents.lines() {
+        if line.trim().is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let (sequent, _) =
+            Type::parse_sequent(line).unwrap_or_else(|e| panic!("{}", e.render(line)));
+        let Some(proof) = Prover::default().prove("Proof", &sequent) else {
+            printed.push(format!("{}\n  no proof", line));
+            continue;
+        };
+        // The net is well-typed, and so is the definition that builds it.
+        if let Err(e) = crate::types::check(&proof.net) {
+            panic!("the proof of {} isn't well-typed: {}", line, e);
+        }
+        let mut loader = crate::syntax::module::Loader::default();
+        let book = loader
+            .load_source("proof.line", proof.source.clone(), file.parent().unwrap())
+            .unwrap_or_else(|e| panic!("{}", e.render(&proof.source)));
+        let mut compiler = crate::syntax::compiler::Compiler::default();
+        if let Err(errors) = compiler.compile_book(book) {
+            panic!(
+                "{}",
+                join_with(
+                    errors
+                        .iter()
+                        .map(|e| e.to_diagnostic().render(&proof.source)),
+                    "\n".to_string()
+                )
+            );
+        }
+        let scheme = match &compiler.global_nets["Proof"].signature {
+            Ok(scheme) => scheme,
+            Err((e, _)) => panic!("the proof of {} isn't well-typed: {}", line, e),
+        };
+        // The proof has the formulas of the sequent, up to the names of their variables.
+        let instance = |a: &[Type], b: &[Type]| {
+            let mut subst = std::collections::BTreeMap::new();
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|(a, b)| crate::types::is_instance(a, b, &mut subst))
+        };
+        let signature = scheme.show_in(Notation::Unicode);
+        assert!(
+            instance(&scheme.ports, &sequent) && instance(&sequent, &scheme.ports),
+            "the proof of {} has another signature: {}",
+            line,
+            signature
+        );
+        printed.push(format!("{}\n{}{}", line, proof.source, signature));
+    }
+    insta::assert_snapshot!(
+        format!("{}/proofs", file.display()),
+        join_with(printed.into_iter(), "\n\n".to_string())
+    );
+}
+
+#[test]
+fn repl_session() {
+    use crate::repl::Repl;
+    use crate::types::show::Notation;
+    let mut file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    file.push("tests/session.repl");
+    let contents = std::fs::read_to_string(&file).unwrap();
+    let mut repl = Repl::new(Notation::Unicode);
+    let mut transcript = String::new();
+    for line in contents.lines() {
+        let prompt = if repl.is_pending() { "|" } else { ">" };
+        transcript.push_str(&format!("{} {}\n", prompt, line));
+        transcript.push_str(&repl.read_line(line));
+    }
+    transcript.push_str(&repl.flush());
+    insta::assert_snapshot!(format!("{}/session", file.display()), transcript);
+}
+
+#[test]
+fn language_server() {
+    use crate::lsp::{read_message, run, write_message};
+    use serde_json::json;
+    let uri = "file:///project/main.line";
+    let text = "\
+Id(out) {
+  a = b
+  Par(a b) = out
+}
+
+Main(out) {
+  Id(x)
+  Times(x)(_y) = out
+}
+
+Clash(res) {
+  One = x
+  Weak(x)[One] = res
+}
+
+Bad(b d out) {
+  a = b
+  c = d
+  Par(a c) = out
+}
+";
+    let at = |id: usize, method: &str, line: usize, character: usize| {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+            },
+        })
+    };
+    let script = [
+        json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {} }),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": { "uri": uri, "languageId": "line", "version": 1, "text": text },
+            },
+        }),
+        // The multicut of `Main`, a wire of `Id` and a wire of `Main`.
+        at(1, "textDocument/hover", 6, 2),
+        at(2, "textDocument/hover", 2, 6),
+        at(3, "textDocument/hover", 7, 8),
+        at(4, "textDocument/definition", 6, 3),
+        at(5, "textDocument/completion", 7, 2),
+        at(6, "textDocument/formatting", 0, 0),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{ "text": "Main(out) {\n  Par(a b) = out\n}\n" }],
+            },
+        }),
+        json!({ "jsonrpc": "2.0", "id": 7, "method": "shutdown" }),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ];
+    let mut input = vec![];
+    for message in &script {
+        write_message(&mut input, message).unwrap();
+    }
+    let mut output = vec![];
+    run(&mut input.as_slice(), &mut output).unwrap();
+    let mut replies = vec![];
+    let mut output = output.as_slice();
+    while let Some(reply) = read_message(&mut output).unwrap() {
+        replies.push(serde_json::to_string_pretty(&reply).unwrap());
+    }
+    insta::assert_snapshot!(join_with(replies.into_iter(), "\n".to_string()));
+}
+
+#[test]
+fn formatting() {
+    use crate::syntax::format::format;
+    let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    d.push("tests");
+    // The nets of every definition of `source`, shown, and the definitions that failed,
+    // or the message of the error that stopped it from loading.
+    let compile = |file: &std::path::Path, source: String| {
+        let mut loader = crate::syntax::module::Loader::default();
+        let file_name = file.file_name().unwrap().to_str().unwrap();
+        let book = loader
+            .load_source(file_name, source, file.parent().unwrap())
+            .map_err(|e| e.message)?;
+        let mut compiler = crate::syntax::compiler::Compiler::default();
+        let _ = compiler.compile_book(book);
+        let nets: Vec<String> = compiler
+            .global_nets
+            .iter()
+            .map(|(name, definition)| {
+                let mut scope = std::collections::BTreeMap::new();
+                let show_agent = |x| format!("{:?}", x);
+                let net = definition.net.show_net(&show_agent, &mut scope, 0);
+                format!("{}\n{}", name, net)
+            })
+            .collect();
+        Ok::<_, String>((nets, compiler.failed))
+    };
+    for file in glob(&(d.to_str().unwrap().to_string() + "/**/*.line")).unwrap() {
+        let file = file.unwrap();
+        let contents = std::fs::read_to_string(&file).unwrap();
+        // Books that don't parse can't be formatted.
+        let Ok(formatted) = format(&contents, "input.line") else {
+            continue;
+        };
+        insta::assert_snapshot!(format!("{}/format", file.display()), formatted);
+        let again = format(&formatted, "formatted.line")
+            .unwrap_or_else(|e| panic!("{}: {}", file.display(), e.render(&formatted)));
+        assert_eq!(
+            formatted,
+            again,
+            "{} isn't formatted idempotently",
+            file.display()
+        );
+        assert_eq!(
+            compile(&file, contents),
+            compile(&file, formatted),
+            "{} compiles to other nets once formatted",
+            file.display()
+        );
+    }
+}
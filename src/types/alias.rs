@@ -0,0 +1,70 @@
+//! Named formulas, as in `type Bool = 1 ⊕ 1` or `type Pair(A, B) = A ⊗ B`.
+use super::{is_instance, Type};
+use crate::syntax::Span;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alias {
+    pub name: String,
+    /// Ids of the parameters, which are the only free variables of `body`.
+    pub params: Vec<usize>,
+    pub body: Type,
+    /// Names of the parameters and of the variables bound in `body`.
+    pub names: BTreeMap<usize, String>,
+    pub span: Span,
+}
+
+impl Alias {
+    /// The body with the parameters replaced by `args`. The variables bound in the body are
+    /// given new ids by `fresh`, which gets their name, so they don't capture those of `args`.
+    pub fn expand(&self, args: &[Type], mut fresh: impl FnMut(&str) -> usize) -> Type {
+        let mut body = self.body.clone();
+        let mut map = BTreeMap::new();
+        for id in body.ids() {
+            if !self.params.contains(&id) {
+                let name = self.names.get(&id).map_or("x", |x| x.as_str());
+                map.insert(id, fresh(name));
+            }
+        }
+        body.replace_vars(&|id| *map.get(&id).unwrap_or(&id));
+        // Parameters are the only `Var`s of the body, since bound variables are `Eigenvar`s.
+        let args: BTreeMap<usize, &Type> = self.params.iter().copied().zip(args).collect();
+        fn go(t: Type, args: &BTreeMap<usize, &Type>) -> Type {
+            let go = |t: Box<Type>| Box::new(go(*t, args));
+            match t {
+                Type::Var(id, dual) => match args.get(&id) {
+                    Some(arg) if dual => !(*arg).clone(),
+                    Some(arg) => (*arg).clone(),
+                    None => Type::Var(id, dual),
+                },
+                Type::Times(a, b) => Type::Times(go(a), go(b)),
+                Type::Par(a, b) => Type::Par(go(a), go(b)),
+                Type::Plus(a, b) => Type::Plus(go(a), go(b)),
+                Type::With(a, b) => Type::With(go(a), go(b)),
+                Type::Ofc(a) => Type::Ofc(go(a)),
+                Type::Why(a) => Type::Why(go(a)),
+                Type::All(id, a) => Type::All(id, go(a)),
+                Type::Any(id, a) => Type::Any(id, go(a)),
+                t => t,
+            }
+        }
+        go(body, &args)
+    }
+    /// The arguments that make this alias stand for `t`, if there are any.
+    pub fn matches(&self, t: &Type) -> Option<Vec<Type>> {
+        // An alias that is just a parameter would stand for every formula.
+        if matches!(self.body, Type::Var(..)) {
+            return None;
+        }
+        let mut subst = BTreeMap::new();
+        if !is_instance(&self.body, t, &mut subst) {
+            return None;
+        }
+        Some(
+            self.params
+                .iter()
+                .map(|x| subst.remove(x).unwrap_or(Type::Hole))
+                .collect(),
+        )
+    }
+}
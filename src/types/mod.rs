@@ -1,8 +1,12 @@
-use crate::net::{Cell, Tree};
+use crate::net::{Arg, Net, PartitionOrBox, SymbolId, Tree, VarId};
+use crate::syntax::Diagnostic;
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 
+pub mod alias;
+pub mod prove;
+pub mod scheme;
 pub mod show;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -29,8 +33,26 @@ pub enum Type {
     Eigenvar(usize, bool),
     /// A formula imṕlicitly introduced in an additive.
     Hole,
+}
 
-    Error,
+impl Type {
+    /// Parses a formula written as `show` prints it, along with the names of its variables.
+    /// Variables bound by a quantifier are `Eigenvar`s, and free ones are `Var`s.
+    pub fn parse(source: &str) -> Result<(Type, BTreeMap<usize, String>), Diagnostic> {
+        crate::syntax::parser::parse_formula(source, "<formula>")
+    }
+    /// Parses a sequent, as in `|- A', A ⊗ B`, along with the names of its variables.
+    pub fn parse_sequent(source: &str) -> Result<(Vec<Type>, BTreeMap<usize, String>), Diagnostic> {
+        crate::syntax::parser::parse_sequent(source, "<sequent>")
+    }
+}
+
+impl std::str::FromStr for Type {
+    type Err = Diagnostic;
+
+    fn from_str(source: &str) -> Result<Type, Diagnostic> {
+        Type::parse(source).map(|(t, _)| t)
+    }
 }
 
 impl std::ops::Not for Type {
@@ -53,413 +75,725 @@ impl std::ops::Not for Type {
             Type::All(a, b) => Type::Any(a, Box::new(!*b)),
             Type::Any(a, b) => Type::All(a, Box::new(!*b)),
             Type::Hole => Type::Hole,
-            Type::Error => Type::Error,
         }
     }
 }
 
 impl Type {
-    fn replace(&mut self, k: (usize, bool), v: Type) {
-        match self {
-            Type::Var(ka, kb) if (*ka, *kb) == k => {
-                *self = v;
-            }
-            Type::Var(..) => {}
-            Type::Times(a, b) | Type::Par(a, b) | Type::Plus(a, b) | Type::With(a, b) => {
-                a.replace(k, v.clone());
-                b.replace(k, v);
-            }
-            Type::Ofc(a) | Type::Why(a) | Type::All(_, a) | Type::Any(_, a) => {
-                a.replace(k, v);
-            }
-            Type::One
-            | Type::False
-            | Type::Zero
-            | Type::True
-            | Type::Hole
-            | Type::Error
-            | Type::Eigenvar(_, _) => (),
-        }
-    }
+    /// Renames every variable with `f`, including eigenvariables and the ones bound by quantifiers.
     fn replace_vars(&mut self, f: &impl Fn(usize) -> usize) {
         match self {
-            Type::Var(ka, _) => {
+            Type::Var(ka, _) | Type::Eigenvar(ka, _) => {
                 *ka = f(*ka);
             }
             Type::Times(a, b) | Type::Par(a, b) | Type::Plus(a, b) | Type::With(a, b) => {
                 a.replace_vars(f);
                 b.replace_vars(f);
             }
-            Type::Ofc(a) | Type::Why(a) | Type::Any(_, a) | Type::All(_, a) => {
+            Type::Ofc(a) | Type::Why(a) => {
                 a.replace_vars(f);
             }
-            Type::One | Type::False | Type::Zero | Type::True | Type::Hole | Type::Error => (),
-            _ => todo!(),
+            Type::Any(id, a) | Type::All(id, a) => {
+                *id = f(*id);
+                a.replace_vars(f);
+            }
+            Type::One | Type::False | Type::Zero | Type::True | Type::Hole => (),
         }
     }
-    fn var_set(&self) -> BTreeSet<usize> {
+    /// The ids of every variable that `replace_vars` renames.
+    fn ids(&self) -> BTreeSet<usize> {
         match self {
-            Type::Var(ka, _) => BTreeSet::from([*ka]),
+            Type::Var(ka, _) | Type::Eigenvar(ka, _) => BTreeSet::from([*ka]),
             Type::Times(a, b) | Type::Par(a, b) | Type::Plus(a, b) | Type::With(a, b) => {
-                let mut vs = a.as_ref().var_set();
-                vs.append(&mut b.as_ref().var_set());
+                let mut vs = a.as_ref().ids();
+                vs.append(&mut b.as_ref().ids());
+                vs
+            }
+            Type::Ofc(a) | Type::Why(a) => a.as_ref().ids(),
+            Type::All(id, a) | Type::Any(id, a) => {
+                let mut vs = a.as_ref().ids();
+                vs.insert(*id);
                 vs
             }
-            Type::Ofc(a) | Type::Why(a) | Type::All(_, a) | Type::Any(_, a) => a.as_ref().var_set(),
-            Type::One
-            | Type::False
-            | Type::Zero
-            | Type::True
-            | Type::Hole
-            | Type::Error
-            | Type::Eigenvar(..) => BTreeSet::new(),
+            Type::One | Type::False | Type::Zero | Type::True | Type::Hole => BTreeSet::new(),
         }
     }
-}
-pub fn infer(trees: Vec<Tree>) -> Vec<Type> {
-    #[derive(Default)]
-    struct State {
-        tree_vars: BTreeMap<usize, Type>,
-        vars_concrete: BTreeMap<(usize, bool), Type>,
-        new_var: usize,
-    }
-    impl State {
-        fn make_new_var(&mut self) -> usize {
-            self.new_var += 1;
-            self.new_var - 1
-        }
-
-        pub fn var_set(&self, tree: &Type) -> BTreeSet<usize> {
-            let mut set = BTreeSet::new();
-            for i in tree.var_set() {
-                set.insert(i);
-                if let Some(i) = self.vars_concrete.get(&(i, false)) {
-                    set.append(&mut self.var_set(i))
+    /// The formula with the variable or eigenvariable `id` replaced by `with`, or its dual.
+    fn substitute(&self, id: usize, with: &Type) -> Type {
+        let go = |t: &Type| Box::new(t.substitute(id, with));
+        match self {
+            Type::Var(ka, dual) | Type::Eigenvar(ka, dual) if *ka == id => {
+                if *dual {
+                    !with.clone()
+                } else {
+                    with.clone()
                 }
             }
-            set
+            Type::Times(a, b) => Type::Times(go(a), go(b)),
+            Type::Par(a, b) => Type::Par(go(a), go(b)),
+            Type::Plus(a, b) => Type::Plus(go(a), go(b)),
+            Type::With(a, b) => Type::With(go(a), go(b)),
+            Type::Ofc(a) => Type::Ofc(go(a)),
+            Type::Why(a) => Type::Why(go(a)),
+            // A quantifier that binds `id` shadows it.
+            Type::All(ka, _) | Type::Any(ka, _) if *ka == id => self.clone(),
+            Type::All(ka, a) => Type::All(*ka, go(a)),
+            Type::Any(ka, a) => Type::Any(*ka, go(a)),
+            t => t.clone(),
         }
-        pub fn make_var_concrete(&mut self, id: usize, flip: bool, b: Type) -> Type {
-            match self.vars_concrete.get(&(id, flip)) {
-                Some(a) => self.unify(a.clone(), b.clone()),
-                None => {
-                    self.vars_concrete.insert((id, flip), b.clone());
-                    self.vars_concrete.insert((id, !flip), !b.clone());
-                    b
-                }
+    }
+    /// Whether the variable or eigenvariable `id` appears free in the formula.
+    fn mentions(&self, id: usize) -> bool {
+        match self {
+            Type::Var(ka, _) | Type::Eigenvar(ka, _) => *ka == id,
+            Type::Times(a, b) | Type::Par(a, b) | Type::Plus(a, b) | Type::With(a, b) => {
+                a.mentions(id) || b.mentions(id)
             }
+            Type::All(ka, a) | Type::Any(ka, a) => *ka != id && a.mentions(id),
+            Type::Ofc(a) | Type::Why(a) => a.mentions(id),
+            Type::One | Type::False | Type::Zero | Type::True | Type::Hole => false,
         }
-
-        fn unify(&mut self, a: Type, b: Type) -> Type {
-            match (a, b) {
-                (Type::Hole, a) => a,
-                (a, Type::Hole) => a,
-                (Type::One, Type::One) => Type::One,
-                (Type::False, Type::False) => Type::False,
-                (Type::Zero, Type::Zero) => Type::Zero,
-                (Type::True, Type::True) => Type::True,
-                (Type::Times(a0, a1), Type::Times(b0, b1)) => Type::Times(
-                    Box::new(self.unify(*a0, *b0)),
-                    Box::new(self.unify(*a1, *b1)),
-                ),
-                (Type::Par(a0, a1), Type::Par(b0, b1)) => Type::Par(
-                    Box::new(self.unify(*a0, *b0)),
-                    Box::new(self.unify(*a1, *b1)),
-                ),
-                (Type::Plus(a0, a1), Type::Plus(b0, b1)) => Type::Plus(
-                    Box::new(self.unify(*a0, *b0)),
-                    Box::new(self.unify(*a1, *b1)),
-                ),
-                (Type::With(a0, a1), Type::With(b0, b1)) => Type::With(
-                    Box::new(self.unify(*a0, *b0)),
-                    Box::new(self.unify(*a1, *b1)),
-                ),
-                (Type::Why(a), Type::Why(b)) => Type::Why(Box::new(self.unify(*a, *b))),
-                (Type::Ofc(a), Type::Ofc(b)) => Type::Ofc(Box::new(self.unify(*a, *b))),
-                // TODO: Is this correct?
-                (Type::Var(a0, a1), Type::Var(b0, b1)) => {
-                    if a0 == b0 && a1 != b1 {
-                        return Type::Error;
-                    }
-                    if a0 == b0 {
-                        return Type::Var(a0, a1);
-                    }
-                    match (
-                        self.vars_concrete.get(&(a0, a1)),
-                        self.vars_concrete.get(&(b0, b1)),
-                    ) {
-                        (Some(a), Some(b)) => self.unify(a.clone(), b.clone()),
-                        (None, Some(b)) => self.make_var_concrete(a0, a1, b.clone()),
-                        (Some(a), None) => self.make_var_concrete(b0, b1, a.clone()),
-                        (None, None) => {
-                            self.vars_concrete.insert((a0, a1), Type::Var(b0, b1));
-                            self.vars_concrete.insert((a0, !a1), Type::Var(b0, !b1));
-                            Type::Var(b0, b1)
-                        }
-                    }
-                }
-                (Type::Var(a0, a1), b) | (b, Type::Var(a0, a1)) => {
-                    self.make_var_concrete(a0, a1, b)
-                }
-                a => {
-                    eprintln!("Unification error: {:?}", a);
-                    Type::Error
-                }
-            }
+    }
+}
+/// Whether `declared` is an instance of `inferred`, with the propositional variables of
+/// `inferred` replaced according to `subst`, which is extended as needed.
+/// The variables of `declared` are fixed, so it can't be more general than `inferred`.
+pub fn is_instance(inferred: &Type, declared: &Type, subst: &mut BTreeMap<usize, Type>) -> bool {
+    // Pairs of variables bound by the quantifiers around the current position.
+    fn go(
+        inferred: &Type,
+        declared: &Type,
+        subst: &mut BTreeMap<usize, Type>,
+        bound: &mut Vec<(usize, usize)>,
+    ) -> bool {
+        use Type::*;
+        // `_` matches anything, on either side.
+        if matches!(inferred, Hole) || matches!(declared, Hole) {
+            return true;
+        }
+        let bound_var = |t: &Type| match t {
+            Var(id, pol) | Eigenvar(id, pol) => Some((*id, *pol)),
+            _ => None,
+        };
+        if let Some((i, ipol)) = bound_var(inferred)
+            && let Some(pair) = bound.iter().rev().find(|(x, _)| *x == i)
+        {
+            return bound_var(declared).is_some_and(|(d, dpol)| d == pair.1 && ipol == dpol);
+        }
+        if let Some((d, _)) = bound_var(declared)
+            && bound.iter().any(|(_, x)| *x == d)
+        {
+            return false;
         }
-        fn freshen_vars(&mut self, types: &mut [&mut Type]) {
-            let mut map = BTreeMap::new();
-            for t in &mut *types {
-                for old in t.var_set() {
-                    if !map.contains_key(&old) {
-                        let new = self.make_new_var();
-                        map.insert(old, new);
+        match (inferred, declared) {
+            (Var(id, pol), declared) => {
+                let declared = if *pol {
+                    !declared.clone()
+                } else {
+                    declared.clone()
+                };
+                match subst.get(id) {
+                    Some(t) => *t == declared,
+                    None => {
+                        subst.insert(*id, declared);
+                        true
                     }
                 }
             }
-            for t in &mut *types {
-                t.replace_vars(&|old| *map.get(&old).unwrap());
+            (One, One) | (False, False) | (Zero, Zero) | (True, True) => true,
+            (Times(a0, a1), Times(b0, b1))
+            | (Par(a0, a1), Par(b0, b1))
+            | (Plus(a0, a1), Plus(b0, b1))
+            | (With(a0, a1), With(b0, b1)) => go(a0, b0, subst, bound) && go(a1, b1, subst, bound),
+            (Ofc(a), Ofc(b)) | (Why(a), Why(b)) => go(a, b, subst, bound),
+            (All(i, a), All(d, b)) | (Any(i, a), Any(d, b)) => {
+                bound.push((*i, *d));
+                let result = go(a, b, subst, bound);
+                bound.pop();
+                result
             }
+            _ => false,
         }
-        fn infer(&mut self, tree: Tree) -> Type {
-            match tree {
-                Tree::Var(id) => match self.tree_vars.entry(id) {
-                    Entry::Occupied(e) => e.remove(),
-                    Entry::Vacant(e) => {
-                        self.new_var += 1;
-                        let new_var = self.new_var - 1;
-                        e.insert(Type::Var(new_var, true));
-                        Type::Var(new_var, false)
-                    }
-                },
-                Tree::Agent(a, b) => {
-                    let Some(cell) = Cell::from_tree(Tree::Agent(a, b)) else {
-                        return Type::Error;
-                    };
-                    match cell {
-                        Cell::Times((a,), (b,)) => {
-                            let ta = self.infer(a);
-                            let tb = self.infer(b);
-                            Type::Times(Box::new(ta), Box::new(tb))
-                        }
-                        Cell::Par((a, b)) => {
-                            let ta = self.infer(a);
-                            let tb = self.infer(b);
-                            Type::Par(Box::new(ta), Box::new(tb))
-                        }
-                        Cell::One() => Type::One,
-                        Cell::False((a,), mut b) => {
-                            b.normal(crate::net::rules::apply_rule);
-                            let mut ports = core::mem::take(&mut b.ports);
-                            ports.iter_mut().for_each(|x| *x = b.substitute_ref(x));
-                            let Ok([mut t0]): Result<[Type; 1], _> = infer(ports.into()).try_into()
-                            else {
-                                return Type::Error;
-                            };
-                            let t1 = self.infer(a);
-                            self.freshen_vars(&mut [&mut t0]);
-                            let tt = self.unify(t0, !t1);
-                            if tt != Type::Error {
-                                Type::False
-                            } else {
-                                Type::Error
-                            }
-                        }
-                        Cell::Left((out,)) => {
-                            Type::Plus(Box::new(self.infer(out)), Box::new(Type::Hole))
-                        }
-                        Cell::Right((out,)) => {
-                            Type::Plus(Box::new(Type::Hole), Box::new(self.infer(out)))
-                        }
-                        Cell::True((out,)) => {
-                            self.infer(out);
-                            Type::True
-                        }
-                        Cell::With((ctx,), mut left, mut right) => {
-                            left.normal(crate::net::rules::apply_rule);
-                            right.normal(crate::net::rules::apply_rule);
-
-                            let mut ports = core::mem::take(&mut left.ports);
-                            ports.iter_mut().for_each(|x| *x = left.substitute_ref(x));
-                            let Ok([mut tvl, mut tcl]): Result<[Type; 2], _> =
-                                infer(ports.into()).try_into()
-                            else {
-                                return Type::Error;
-                            };
-                            self.freshen_vars(&mut [&mut tvl, &mut tcl]);
-
-                            let mut ports = core::mem::take(&mut right.ports);
-                            ports.iter_mut().for_each(|x| *x = right.substitute_ref(x));
-                            let Ok([mut tvr, mut tcr]): Result<[Type; 2], _> =
-                                infer(ports.into()).try_into()
-                            else {
-                                return Type::Error;
-                            };
-                            self.freshen_vars(&mut [&mut tvr, &mut tcr]);
-
-                            let tctx = self.infer(ctx);
-                            let tc = self.unify(tcl, tcr);
-                            if self.unify(!tctx, tc) != Type::Error {
-                                Type::With(Box::new(tvl), Box::new(tvr))
-                            } else {
-                                Type::Error
-                            }
-                        }
-                        Cell::Exp0(mut net) => {
-                            net.normal(crate::net::rules::apply_rule);
-
-                            let mut ports = core::mem::take(&mut net.ports);
-                            ports.iter_mut().for_each(|x| *x = net.substitute_ref(x));
-                            let Ok([mut t]): Result<[Type; 1], _> = infer(ports.into()).try_into()
-                            else {
-                                return Type::Error;
-                            };
-                            self.freshen_vars(&mut [&mut t]);
-
-                            Type::Ofc(Box::new(t))
-                        }
-                        Cell::Exp1((inp,), mut net) => {
-                            net.normal(crate::net::rules::apply_rule);
-
-                            let mut ports = core::mem::take(&mut net.ports);
-                            ports.iter_mut().for_each(|x| *x = net.substitute_ref(x));
-                            let Ok([mut t, inp_t]): Result<[Type; 2], _> =
-                                infer(ports.into()).try_into()
-                            else {
-                                return Type::Error;
-                            };
-                            self.freshen_vars(&mut [&mut t]);
-
-                            let other_inp_t = self.infer(inp);
-                            self.unify(other_inp_t, Type::Ofc(Box::new(!inp_t)));
-
-                            Type::Ofc(Box::new(t))
-                        }
-                        Cell::Weak((ctx,), mut net) => {
-                            let mut ports = core::mem::take(&mut net.ports);
-                            ports.iter_mut().for_each(|x| *x = net.substitute_ref(x));
-                            let Ok([mut t]): Result<[Type; 1], _> = infer(ports.into()).try_into()
-                            else {
-                                return Type::Error;
-                            };
-                            self.freshen_vars(&mut [&mut t]);
+    }
+    go(inferred, declared, subst, &mut vec![])
+}
+/// Why a net isn't well-typed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeError {
+    pub kind: TypeErrorKind,
+    /// The agent whose typing rule failed, or `None` for a cut between two ports.
+    pub agent: Option<SymbolId>,
+    /// How to reach `agent` from the trees being typed.
+    pub path: Vec<Step>,
+    /// Names of the propositional variables of the formulas in `kind`.
+    pub names: BTreeMap<usize, String>,
+}
 
-                            let c_t = self.infer(ctx);
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TypeErrorKind {
+    /// The agent needs two formulas to be the same, but they can't be unified.
+    Clash(Type, Type),
+    /// The eigenvariable of a `∀` appears in its context, in this formula.
+    EscapingEigenvar(Type),
+    /// A variable would have to be a formula that contains it, like `a = a ⊗ b` or `a = a'`.
+    Circular(Type, Type),
+    /// A box of the agent has the wrong number of ports, or the agent has the wrong arguments.
+    Malformed,
+    /// A multicut uses a definition that isn't well-typed.
+    IllTypedDependency(String),
+}
 
-                            self.unify(c_t, !t);
+/// A step from a tree to a subtree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// The `n`th tree being typed, or the `n`th port of a box.
+    Port(usize),
+    /// The `n`th wire of an agent, counting every partition from left to right.
+    Wire(SymbolId, usize),
+    /// The `n`th box of an agent.
+    Box(SymbolId, usize),
+    /// The `n`th redex of a net. Its sides are ports 0 and 1.
+    Redex(usize),
+}
 
-                            Type::Why(Box::new(Type::Hole))
-                        }
-                        Cell::Dere((a,)) => Type::Why(Box::new(self.infer(a))),
-                        Cell::Cntr((a, b)) => {
-                            let a_t = self.infer(a);
-                            let b_t = self.infer(b);
-                            if matches!((&a_t, &b_t), (Type::Why(..), Type::Why(..))) {
-                                self.unify(a_t, b_t)
-                            } else if let (Type::Var(a_id, a_pol), Type::Var(b_id, b_pol)) =
-                                (&a_t, &b_t)
-                            {
-                                let v1 = self.make_new_var();
-                                self.vars_concrete.insert(
-                                    (*a_id, *a_pol),
-                                    Type::Why(Box::new(Type::Var(v1, false))),
-                                );
-                                self.vars_concrete.insert(
-                                    (*a_id, !*a_pol),
-                                    !Type::Why(Box::new(Type::Var(v1, false))),
-                                );
-                                self.vars_concrete.insert(
-                                    (*b_id, *b_pol),
-                                    Type::Why(Box::new(Type::Var(v1, false))),
-                                );
-                                self.vars_concrete.insert(
-                                    (*b_id, !*b_pol),
-                                    !Type::Why(Box::new(Type::Var(v1, false))),
-                                );
-                                Type::Why(Box::new(Type::Var(v1, false)))
-                            } else {
-                                Type::Error
-                            }
-                        }
-                        Cell::All((ctx,), mut net) => {
-                            net.canonical();
-                            let ports = core::mem::take(&mut net.ports);
-                            let Ok([mut ctx_in, mut vars, mut body_in]): Result<[Type; 3], _> =
-                                infer(ports.into()).try_into()
-                            else {
-                                return Type::Error;
-                            };
-                            self.freshen_vars(&mut [&mut ctx_in, &mut vars, &mut body_in]);
-                            let ctx_out = self.infer(ctx);
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Port(n) => write!(f, "port {}", n),
+            Step::Wire(agent, n) => write!(f, "{:?}.{}", agent, n),
+            Step::Box(agent, n) => write!(f, "{:?}[{}]", agent, n),
+            Step::Redex(n) => write!(f, "redex {}", n),
+        }
+    }
+}
 
-                            let var_id = self.make_new_var();
-                            let var_t = Type::Eigenvar(var_id, false);
-                            self.unify(
-                                !vars,
-                                Type::Ofc(Box::new(Type::With(
-                                    Box::new(Type::Par(
-                                        Box::new(var_t.clone()),
-                                        Box::new(!var_t.clone()),
-                                    )),
-                                    Box::new(Type::Par(
-                                        Box::new(!var_t.clone()),
-                                        Box::new(var_t.clone()),
-                                    )),
-                                ))),
-                            );
-                            if self.var_set(&ctx_in).contains(&var_id) {
-                                eprintln!("Bad forall");
-                                return Type::Error;
-                            }
+impl std::fmt::Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            TypeErrorKind::Clash(a, b) => {
+                let mut scope = Type::named_scope(&[a.clone(), b.clone()], &self.names);
+                write!(
+                    f,
+                    "`{}` doesn't match `{}`",
+                    a.show(&mut scope),
+                    b.show(&mut scope)
+                )?
+            }
+            TypeErrorKind::EscapingEigenvar(t) => {
+                let mut scope = Type::named_scope(std::slice::from_ref(t), &self.names);
+                write!(
+                    f,
+                    "The variable of a `∀` escapes into its context `{}`",
+                    t.show(&mut scope)
+                )?
+            }
+            TypeErrorKind::Circular(a, b) => {
+                let mut scope = Type::named_scope(&[a.clone(), b.clone()], &self.names);
+                write!(
+                    f,
+                    "`{}` would have to be `{}`, which contains it",
+                    a.show(&mut scope),
+                    b.show(&mut scope)
+                )?
+            }
+            TypeErrorKind::Malformed => write!(f, "Malformed agent")?,
+            TypeErrorKind::IllTypedDependency(name) => write!(f, "`{}` isn't well-typed", name)?,
+        }
+        if let Some(agent) = &self.agent {
+            write!(f, ", in `{:?}`", agent)?;
+        }
+        if !self.path.is_empty() {
+            let path: Vec<String> = self.path.iter().map(|x| x.to_string()).collect();
+            write!(f, " at {}", path.join(" → "))?;
+        }
+        Ok(())
+    }
+}
 
-                            if self.unify(!ctx_out, ctx_in) == Type::Error {
-                                return Type::Error;
-                            }
-                            Type::All(var_id, Box::new(body_in))
-                        }
-                        Cell::Any((ctx,), mut net) => {
-                            net.canonical();
-                            let ports = core::mem::take(&mut net.ports);
-                            let Ok([mut ctx_in, mut vars, mut body_in]): Result<[Type; 3], _> =
-                                infer(ports.into()).try_into()
-                            else {
-                                return Type::Error;
-                            };
-                            self.freshen_vars(&mut [&mut ctx_in, &mut vars, &mut body_in]);
-                            let ctx_out = self.infer(ctx);
+pub fn infer(trees: Vec<Tree>) -> Result<Vec<Type>, TypeError> {
+    Ok(infer_named(trees, &BTreeMap::new())?.0)
+}
+/// Like `infer`, but propositional variables are named after the net vars they come from.
+/// `var_names` are the names of the net vars, and the names of the type variables are returned.
+pub fn infer_named(
+    trees: Vec<Tree>,
+    var_names: &BTreeMap<VarId, String>,
+) -> Result<(Vec<Type>, BTreeMap<usize, String>), TypeError> {
+    let mut state = State::new(var_names);
+    let mut types = vec![];
+    for (i, tree) in trees.into_iter().enumerate() {
+        state.path = vec![Step::Port(i)];
+        types.push(state.infer(tree)?);
+    }
+    let types = types.into_iter().map(|t| state.resolve(t)).collect();
+    Ok((types, state.names))
+}
+/// Like `infer_named`, but for a net that may not be normalized: the two sides of each
+/// redex must have dual types. Boxes are checked the same way, so nothing is reduced.
+pub fn check(net: &Net) -> Result<(Vec<Type>, BTreeMap<usize, String>), TypeError> {
+    let mut state = State::new(&net.names);
+    state.normal_boxes = false;
+    let mut types = vec![];
+    for (i, tree) in net
+        .substitute_iter(net.ports.iter())
+        .into_iter()
+        .enumerate()
+    {
+        state.path = vec![Step::Port(i)];
+        types.push(state.infer(tree)?);
+    }
+    for (i, (a, b)) in net.redexes.iter().enumerate() {
+        state.path = vec![Step::Redex(i), Step::Port(0)];
+        let ta = state.infer(net.substitute_ref(a))?;
+        state.path = vec![Step::Redex(i), Step::Port(1)];
+        let tb = state.infer(net.substitute_ref(b))?;
+        state.path = vec![Step::Redex(i)];
+        state.cut(ta, tb)?;
+    }
+    let types = types.into_iter().map(|t| state.resolve(t)).collect();
+    Ok((types, state.names))
+}
 
-                            let var_id = self.make_new_var();
-                            let var_t = Type::Var(var_id, false);
-                            self.unify(
-                                !vars,
-                                Type::Ofc(Box::new(Type::With(
-                                    Box::new(Type::Par(
-                                        Box::new(var_t.clone()),
-                                        Box::new(!var_t.clone()),
-                                    )),
-                                    Box::new(Type::Par(
-                                        Box::new(!var_t.clone()),
-                                        Box::new(var_t.clone()),
-                                    )),
-                                ))),
-                            );
+#[derive(Debug, Clone)]
+struct State<'a> {
+    tree_vars: BTreeMap<usize, Type>,
+    /// The formulas that variables stand for. `Var(id, true)` stands for the dual of
+    /// the formula bound to `id`, so both polarities are always bound consistently.
+    subst: BTreeMap<usize, Type>,
+    new_var: usize,
+    var_names: &'a BTreeMap<VarId, String>,
+    names: BTreeMap<usize, String>,
+    /// The path to the tree being typed.
+    path: Vec<Step>,
+    /// Whether boxes are normalized before they're typed, or their redexes are checked.
+    normal_boxes: bool,
+}
+/// Why two formulas can't be unified.
+enum Mismatch {
+    Clash,
+    Circular(Type, Type),
+}
+impl<'a> State<'a> {
+    fn new(var_names: &'a BTreeMap<VarId, String>) -> Self {
+        State {
+            tree_vars: BTreeMap::new(),
+            subst: BTreeMap::new(),
+            new_var: 0,
+            var_names,
+            names: BTreeMap::new(),
+            path: vec![],
+            normal_boxes: true,
+        }
+    }
+    fn make_new_var(&mut self) -> usize {
+        self.new_var += 1;
+        self.new_var - 1
+    }
+    /// Follows the bindings of `t` until it's not a bound variable.
+    fn walk(&self, t: Type) -> Type {
+        match t {
+            Type::Var(id, dual) => match self.subst.get(&id) {
+                Some(bound) if dual => self.walk(!bound.clone()),
+                Some(bound) => self.walk(bound.clone()),
+                None => Type::Var(id, dual),
+            },
+            t => t,
+        }
+    }
+    /// `t` with every bound variable replaced, recursively.
+    /// The occurs check in `bind` makes sure this terminates.
+    fn resolve(&self, t: Type) -> Type {
+        self.resolve_under(t, &mut vec![])
+    }
+    /// Like `resolve`, but the variables in `binders`, which quantifiers around `t` bind,
+    /// are left as they are.
+    fn resolve_under(&self, t: Type, binders: &mut Vec<usize>) -> Type {
+        if let Type::Var(id, _) = &t
+            && binders.contains(id)
+        {
+            return t;
+        }
+        let mut go = |t: Box<Type>| Box::new(self.resolve_under(*t, binders));
+        match self.walk(t) {
+            Type::Times(a, b) => Type::Times(go(a), go(b)),
+            Type::Par(a, b) => Type::Par(go(a), go(b)),
+            Type::Plus(a, b) => Type::Plus(go(a), go(b)),
+            Type::With(a, b) => Type::With(go(a), go(b)),
+            Type::Ofc(a) => Type::Ofc(go(a)),
+            Type::Why(a) => Type::Why(go(a)),
+            Type::All(id, a) => {
+                binders.push(id);
+                let a = self.resolve_under(*a, binders);
+                binders.pop();
+                Type::All(id, Box::new(a))
+            }
+            Type::Any(id, a) => {
+                binders.push(id);
+                let a = self.resolve_under(*a, binders);
+                binders.pop();
+                Type::Any(id, Box::new(a))
+            }
+            t => t,
+        }
+    }
+    fn error(&self, agent: Option<&SymbolId>, kind: TypeErrorKind) -> TypeError {
+        let kind = match kind {
+            TypeErrorKind::Clash(a, b) => TypeErrorKind::Clash(self.resolve(a), self.resolve(b)),
+            TypeErrorKind::Circular(a, b) => {
+                TypeErrorKind::Circular(self.resolve(a), self.resolve(b))
+            }
+            TypeErrorKind::EscapingEigenvar(t) => TypeErrorKind::EscapingEigenvar(self.resolve(t)),
+            kind => kind,
+        };
+        TypeError {
+            kind,
+            agent: agent.cloned(),
+            path: self.path.clone(),
+            names: self.names.clone(),
+        }
+    }
+    /// Unifies the formulas that `agent` needs to be the same. Without an agent,
+    /// they are the two sides of a cut.
+    fn unify_at(&mut self, agent: Option<&SymbolId>, a: Type, b: Type) -> Result<Type, TypeError> {
+        self.unify(a.clone(), b.clone()).map_err(|e| {
+            let kind = match e {
+                Mismatch::Clash => TypeErrorKind::Clash(a, b),
+                Mismatch::Circular(a, b) => TypeErrorKind::Circular(a, b),
+            };
+            self.error(agent, kind)
+        })
+    }
+    /// Unifies the types of two ports that are cut, which must be dual.
+    fn cut(&mut self, a: Type, b: Type) -> Result<(), TypeError> {
+        // A `∀` cut against an `∃` is instantiated with the witness of the `∃`,
+        // which is whatever the variable of the `∃` stands for.
+        let (a, b) = match (self.resolve(a), self.resolve(b)) {
+            (Type::All(x, a), Type::Any(y, b)) | (Type::Any(y, b), Type::All(x, a)) => {
+                let witness = Type::Var(self.make_new_var(), false);
+                (a.substitute(x, &witness), b.substitute(y, &witness))
+            }
+            (a, b) => (a, b),
+        };
+        self.unify_at(None, a, !b)?;
+        Ok(())
+    }
+    /// Binds the variable `id` so that `Var(id, dual)` stands for `t`.
+    fn bind(&mut self, id: usize, dual: bool, t: Type) -> Result<Type, Mismatch> {
+        let bound = if dual { !t.clone() } else { t.clone() };
+        if self.resolve(bound.clone()).mentions(id) {
+            return Err(Mismatch::Circular(Type::Var(id, dual), t));
+        }
+        self.subst.insert(id, bound);
+        Ok(t)
+    }
 
-                            if self.unify(!ctx_out, ctx_in) == Type::Error {
-                                return Type::Error;
-                            }
-                            Type::Any(var_id, Box::new(body_in))
+    fn unify(&mut self, a: Type, b: Type) -> Result<Type, Mismatch> {
+        Ok(match (self.walk(a), self.walk(b)) {
+            (Type::Hole, a) => a,
+            (a, Type::Hole) => a,
+            (Type::One, Type::One) => Type::One,
+            (Type::False, Type::False) => Type::False,
+            (Type::Zero, Type::Zero) => Type::Zero,
+            (Type::True, Type::True) => Type::True,
+            (Type::Times(a0, a1), Type::Times(b0, b1)) => Type::Times(
+                Box::new(self.unify(*a0, *b0)?),
+                Box::new(self.unify(*a1, *b1)?),
+            ),
+            (Type::Par(a0, a1), Type::Par(b0, b1)) => Type::Par(
+                Box::new(self.unify(*a0, *b0)?),
+                Box::new(self.unify(*a1, *b1)?),
+            ),
+            (Type::Plus(a0, a1), Type::Plus(b0, b1)) => Type::Plus(
+                Box::new(self.unify(*a0, *b0)?),
+                Box::new(self.unify(*a1, *b1)?),
+            ),
+            (Type::With(a0, a1), Type::With(b0, b1)) => Type::With(
+                Box::new(self.unify(*a0, *b0)?),
+                Box::new(self.unify(*a1, *b1)?),
+            ),
+            (Type::Eigenvar(a0, a1), Type::Eigenvar(b0, b1)) if (a0, a1) == (b0, b1) => {
+                Type::Eigenvar(a0, a1)
+            }
+            (Type::Why(a), Type::Why(b)) => Type::Why(Box::new(self.unify(*a, *b)?)),
+            (Type::Ofc(a), Type::Ofc(b)) => Type::Ofc(Box::new(self.unify(*a, *b)?)),
+            (Type::All(x, a), Type::All(y, b)) => {
+                let (z, body) = self.unify_bodies((x, *a), (y, *b))?;
+                Type::All(z, Box::new(body))
+            }
+            (Type::Any(x, a), Type::Any(y, b)) => {
+                let (z, body) = self.unify_bodies((x, *a), (y, *b))?;
+                Type::Any(z, Box::new(body.substitute(z, &Type::Var(z, false))))
+            }
+            (Type::Var(a0, a1), Type::Var(b0, b1)) if a0 == b0 => {
+                if a1 != b1 {
+                    // No formula is its own dual.
+                    return Err(Mismatch::Circular(Type::Var(a0, a1), Type::Var(b0, b1)));
+                }
+                Type::Var(a0, a1)
+            }
+            (Type::Var(a0, a1), Type::Var(b0, b1)) => {
+                // The variable that is left keeps the name of the one that is bound.
+                if let Some(name) = self.names.get(&a0)
+                    && !self.names.contains_key(&b0)
+                {
+                    self.names.insert(b0, name.clone());
+                }
+                self.bind(a0, a1, Type::Var(b0, b1))?
+            }
+            (Type::Var(a0, a1), b) | (b, Type::Var(a0, a1)) => self.bind(a0, a1, b)?,
+            _ => return Err(Mismatch::Clash),
+        })
+    }
+    /// Unifies the bodies of two quantifiers, which bind `x` in `a` and `y` in `b`, up to the
+    /// names of their variables. Returns the variable that the unified body is quantified over,
+    /// which is an eigenvariable in it.
+    fn unify_bodies(
+        &mut self,
+        (x, a): (usize, Type),
+        (y, b): (usize, Type),
+    ) -> Result<(usize, Type), Mismatch> {
+        let z = self.make_new_var();
+        let a = a.substitute(x, &Type::Eigenvar(z, false));
+        let b = b.substitute(y, &Type::Eigenvar(z, false));
+        let mut free = a.ids();
+        free.append(&mut b.ids());
+        let body = self.unify(a, b)?;
+        // The variable can't escape the quantifiers into a variable of their context.
+        if free
+            .into_iter()
+            .any(|id| id != z && self.resolve(Type::Var(id, false)).mentions(z))
+        {
+            return Err(Mismatch::Clash);
+        }
+        Ok((z, body))
+    }
+    /// Gives the variables of formulas inferred elsewhere new ids in this state.
+    /// `names` are the names of those variables.
+    fn freshen_vars(&mut self, types: &mut [&mut Type], names: &BTreeMap<usize, String>) {
+        let mut map = BTreeMap::new();
+        for t in &mut *types {
+            for old in t.ids() {
+                if !map.contains_key(&old) {
+                    let new = self.make_new_var();
+                    if let Some(name) = names.get(&old) {
+                        self.names.insert(new, name.clone());
+                    }
+                    map.insert(old, new);
+                }
+            }
+        }
+        for t in &mut *types {
+            t.replace_vars(&|old| *map.get(&old).unwrap());
+        }
+    }
+    /// Infers the types of the ports of `net`, the `index`th box of `agent`.
+    fn infer_box(
+        &mut self,
+        agent: &SymbolId,
+        index: usize,
+        mut net: Net,
+    ) -> Result<Vec<Type>, TypeError> {
+        let result = if self.normal_boxes {
+            match agent {
+                SymbolId::All | SymbolId::Any => net.canonical(),
+                SymbolId::Weak => (),
+                _ => net.normal(crate::net::rules::apply_rule),
+            }
+            infer_named(net.substitute_iter(net.ports.iter()), &net.names)
+        } else {
+            check(&net)
+        };
+        let (mut types, names) = result.map_err(|mut e| {
+            let mut path = self.path.clone();
+            path.push(Step::Box(agent.clone(), index));
+            path.append(&mut e.path);
+            e.path = path;
+            e
+        })?;
+        self.freshen_vars(&mut types.iter_mut().collect::<Vec<_>>(), &names);
+        Ok(types)
+    }
+    /// Infers the type of the `index`th wire of `agent`.
+    fn infer_wire(
+        &mut self,
+        agent: &SymbolId,
+        index: usize,
+        tree: Tree,
+    ) -> Result<Type, TypeError> {
+        self.path.push(Step::Wire(agent.clone(), index));
+        let t = self.infer(tree);
+        self.path.pop();
+        t
+    }
+    fn infer(&mut self, tree: Tree) -> Result<Type, TypeError> {
+        match tree {
+            Tree::Var(id) => Ok(match self.tree_vars.entry(id) {
+                Entry::Occupied(e) => e.remove(),
+                Entry::Vacant(e) => {
+                    self.new_var += 1;
+                    let new_var = self.new_var - 1;
+                    if let Some(name) = self.var_names.get(&id) {
+                        self.names.insert(new_var, name.clone());
+                    }
+                    e.insert(Type::Var(new_var, true));
+                    Type::Var(new_var, false)
+                }
+            }),
+            Tree::Agent(agent, args) => {
+                let shape_matches = agent.args().len() == args.len()
+                    && agent.args().iter().zip(&args).all(|x| match x {
+                        (Arg::Partition(n), PartitionOrBox::Partition(trees)) => *n == trees.len(),
+                        (Arg::Box(_), PartitionOrBox::Box(_)) => true,
+                        _ => false,
+                    });
+                if !shape_matches {
+                    return Err(self.error(Some(&agent), TypeErrorKind::Malformed));
+                }
+                // Boxes are typed before wires.
+                let mut boxes = vec![];
+                let mut trees = vec![];
+                for arg in args {
+                    match arg {
+                        PartitionOrBox::Box(net) => {
+                            boxes.push(self.infer_box(&agent, boxes.len(), net)?)
                         }
+                        PartitionOrBox::Partition(partition) => trees.extend(partition),
                     }
                 }
+                let mut wires = vec![];
+                for tree in trees {
+                    wires.push(self.infer_wire(&agent, wires.len(), tree)?);
+                }
+                self.rule(&agent, wires, boxes)
             }
         }
     }
-    let mut state = State::default();
-    let mut types: Vec<_> = trees.into_iter().map(|x| state.infer(x)).collect();
-    for (k, v) in state.vars_concrete.into_iter() {
-        for i in types.iter_mut() {
-            i.replace(k, v.clone());
+    /// The type of the principal port of `agent`, from the types of its wires, counting
+    /// every partition from left to right, and of the ports of each of its boxes.
+    fn rule(
+        &mut self,
+        agent: &SymbolId,
+        wires: Vec<Type>,
+        boxes: Vec<Vec<Type>>,
+    ) -> Result<Type, TypeError> {
+        let at = Some(agent);
+        let malformed = |this: &Self| this.error(at, TypeErrorKind::Malformed);
+        let mut boxes = boxes.into_iter();
+        macro_rules! next_box {
+            () => {
+                match boxes.next().map(TryInto::try_into) {
+                    Some(Ok(ports)) => ports,
+                    _ => return Err(malformed(self)),
+                }
+            };
+        }
+        let mut wires = wires.into_iter();
+        macro_rules! next_wire {
+            () => {
+                match wires.next() {
+                    Some(t) => t,
+                    None => return Err(malformed(self)),
+                }
+            };
         }
+        Ok(match agent {
+            SymbolId::Times => {
+                let (ta, tb) = (next_wire!(), next_wire!());
+                Type::Times(Box::new(ta), Box::new(tb))
+            }
+            SymbolId::Par => {
+                let (ta, tb) = (next_wire!(), next_wire!());
+                Type::Par(Box::new(ta), Box::new(tb))
+            }
+            SymbolId::One => Type::One,
+            SymbolId::False => {
+                let [t0] = next_box!();
+                let t1 = next_wire!();
+                self.unify_at(at, t0, !t1)?;
+                Type::False
+            }
+            SymbolId::Left => Type::Plus(Box::new(next_wire!()), Box::new(Type::Hole)),
+            SymbolId::Right => Type::Plus(Box::new(Type::Hole), Box::new(next_wire!())),
+            SymbolId::True => {
+                next_wire!();
+                Type::True
+            }
+            SymbolId::With => {
+                let [tvl, tcl] = next_box!();
+                let [tvr, tcr] = next_box!();
+                let tctx = next_wire!();
+                let tc = self.unify_at(at, tcl, tcr)?;
+                self.unify_at(at, !tctx, tc)?;
+                Type::With(Box::new(tvl), Box::new(tvr))
+            }
+            SymbolId::Exp0 => {
+                let [t] = next_box!();
+                Type::Ofc(Box::new(t))
+            }
+            SymbolId::Exp1 => {
+                let [t, inp_t] = next_box!();
+                let other_inp_t = next_wire!();
+                self.unify_at(at, other_inp_t, Type::Ofc(Box::new(!inp_t)))?;
+                Type::Ofc(Box::new(t))
+            }
+            SymbolId::Weak => {
+                let [t] = next_box!();
+                let c_t = next_wire!();
+                self.unify_at(at, c_t, !t)?;
+                Type::Why(Box::new(Type::Hole))
+            }
+            SymbolId::Dere => Type::Why(Box::new(next_wire!())),
+            SymbolId::Cntr => {
+                let (a_t, b_t) = (next_wire!(), next_wire!());
+                // Both sides are the same `?` formula.
+                let why = match (self.walk(a_t.clone()), self.walk(b_t.clone())) {
+                    (t @ Type::Why(..), _) | (_, t @ Type::Why(..)) => t,
+                    _ => Type::Why(Box::new(Type::Var(self.make_new_var(), false))),
+                };
+                let why = self.unify_at(at, a_t, why)?;
+                self.unify_at(at, why, b_t)?
+            }
+            // A hole can have any type, which the rest of the net decides.
+            SymbolId::Hole => Type::Var(self.make_new_var(), false),
+            SymbolId::All | SymbolId::Any => {
+                let [ctx_in, vars, body_in] = next_box!();
+                let ctx_out = next_wire!();
+                let universal = *agent == SymbolId::All;
+                let var_id = self.make_new_var();
+                // The variable of a `∀` can't be unified with anything else.
+                let var_t = if universal {
+                    Type::Eigenvar(var_id, false)
+                } else {
+                    Type::Var(var_id, false)
+                };
+                self.unify_at(
+                    at,
+                    !vars,
+                    Type::Ofc(Box::new(Type::With(
+                        Box::new(Type::Par(Box::new(var_t.clone()), Box::new(!var_t.clone()))),
+                        Box::new(Type::Par(Box::new(!var_t.clone()), Box::new(var_t))),
+                    ))),
+                )?;
+                if universal && self.resolve(ctx_in.clone()).mentions(var_id) {
+                    return Err(self.error(at, TypeErrorKind::EscapingEigenvar(ctx_in)));
+                }
+                self.unify_at(at, !ctx_out, ctx_in)?;
+                if universal {
+                    Type::All(var_id, Box::new(body_in))
+                } else {
+                    // The variable of an `∃` stands for its witness, which the rest of the net
+                    // may still unify with other formulas. The `∃` binds a fresh variable
+                    // instead, in place of the witness in its body.
+                    let bound = self.make_new_var();
+                    let body = match self.resolve(Type::Var(var_id, false)) {
+                        Type::Var(witness, dual) => self
+                            .resolve(body_in)
+                            .substitute(witness, &Type::Var(bound, dual)),
+                        _ => self.resolve(body_in),
+                    };
+                    Type::Any(bound, Box::new(body))
+                }
+            }
+        })
     }
-    types
 }
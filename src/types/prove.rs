@@ -0,0 +1,748 @@
+//! Proof search for multiplicative-additive linear logic and its second order, with a bound
+//! on the contractions of `?` formulas. The proofs it finds are cut-free, and they're built
+//! with the simplicity operations, so they're nets, along with the `.line` instructions
+//! that perform the same operations.
+use super::Type;
+use crate::net::{reorder, Arg, GraftArg, Net, SymbolId};
+use crate::util::join_with;
+use std::collections::BTreeMap;
+
+/// A net whose ports have the formulas of a sequent, in the same order.
+#[derive(Debug, Clone)]
+pub struct Proof {
+    pub net: Net,
+    /// A definition that compiles to `net`.
+    pub source: String,
+}
+
+/// Searches for proofs of sequents.
+#[derive(Debug, Clone)]
+pub struct Prover {
+    /// How many times a `?` formula can be contracted in each branch of a proof.
+    pub contractions: usize,
+}
+
+impl Default for Prover {
+    fn default() -> Self {
+        Prover { contractions: 2 }
+    }
+}
+
+impl Prover {
+    /// A proof of `sequent`, written as a definition called `name`. Proofs that contract
+    /// less are found first.
+    pub fn prove(&self, name: &str, sequent: &[Type]) -> Option<Proof> {
+        let mut search = Search {
+            next_id: sequent
+                .iter()
+                .flat_map(Type::ids)
+                .max()
+                .map_or(0, |x| x + 1),
+            opened: vec![],
+            witnesses: BTreeMap::new(),
+            renamed: BTreeMap::new(),
+        };
+        let step = (0..=self.contractions).find_map(|c| search.search(sequent, c))?;
+        let mut builder = Builder::default();
+        let piece = builder.build(step, sequent.len());
+        let source = format!(
+            "{}({}) {{\n{}}}\n",
+            name,
+            join_with(piece.ports.into_iter(), " ".to_string()),
+            builder
+                .instructions
+                .iter()
+                .map(|x| format!("  {}\n", x))
+                .collect::<String>()
+        );
+        Some(Proof {
+            net: piece.net,
+            source,
+        })
+    }
+}
+
+/// The last rule of a derivation, with the index of its principal formula in the conclusion.
+/// Each premise has the rest of the conclusion in order, followed by the new formulas.
+#[derive(Debug)]
+enum Step {
+    /// `|- A, A'`.
+    Axiom,
+    /// `|- x, x', ?V` for a variable opened by a quantifier, where `V` is the formula that
+    /// the quantifier relates the variable with. These are the indices of the three formulas.
+    /// In the box of a `!`, whose context is `?V`, it's `|- x, x', V`, and `linear` is set.
+    Variable {
+        var: usize,
+        dual: usize,
+        vars: usize,
+        linear: bool,
+    },
+    One,
+    True(usize),
+    False(usize, Box<Step>),
+    Par(usize, Box<Step>),
+    /// The indices of the formulas that go to the left premise.
+    Times(usize, Vec<usize>, Box<Step>, Box<Step>),
+    With(usize, Box<Step>, Box<Step>),
+    Left(usize, Box<Step>),
+    Right(usize, Box<Step>),
+    /// The premise has the variables formula before the body.
+    All(usize, Box<Step>),
+    /// The premise has the variables formula before the body.
+    Any(usize, Box<Step>),
+    /// The conclusion is `|- !A`.
+    Exp0(Box<Step>),
+    /// The conclusion is `|- !A, ?B`, and the premise is `|- A, B`.
+    Exp1(usize, Box<Step>),
+    Dere(usize, Box<Step>),
+    Weak(usize, Box<Step>),
+    /// The premise has a copy of the formula at the end.
+    Cntr(usize, Box<Step>),
+}
+
+struct Search {
+    next_id: usize,
+    /// The variables that quantifiers have been opened with, in order.
+    opened: Vec<usize>,
+    /// The variables opened by an `∃`, with the formula that each one stands for, once a
+    /// leaf has paired it with one.
+    witnesses: BTreeMap<usize, Option<Type>>,
+    /// The propositional variables of the bodies of the `∃`s, which are renamed when they're
+    /// opened, by the variable they were renamed from. A witness comes from the context of an
+    /// `∃`, so it can't mention the variables of its body.
+    renamed: BTreeMap<usize, usize>,
+}
+
+/// The formula that the box of a quantifier relates its variable `x` with.
+fn vars(x: usize) -> Type {
+    Type::Why(Box::new(relation(x)))
+}
+
+/// `V` in the formula `?V` of the variable `x`.
+fn relation(x: usize) -> Type {
+    let var = || Box::new(Type::Eigenvar(x, false));
+    let dual = || Box::new(Type::Eigenvar(x, true));
+    Type::Plus(
+        Box::new(Type::Times(dual(), var())),
+        Box::new(Type::Times(var(), dual())),
+    )
+}
+
+fn without(sequent: &[Type], i: usize) -> Vec<Type> {
+    let mut rest = sequent.to_vec();
+    rest.remove(i);
+    rest
+}
+
+fn with(mut sequent: Vec<Type>, formulas: impl IntoIterator<Item = Type>) -> Vec<Type> {
+    sequent.extend(formulas);
+    sequent
+}
+
+/// Weakens the formulas at `indices`, which are in increasing order, so that `step` proves
+/// the rest of the sequent.
+fn weaken(indices: &[usize], step: Step) -> Step {
+    indices
+        .iter()
+        .fold(step, |step, i| Step::Weak(*i, Box::new(step)))
+}
+
+impl Search {
+    fn open(&mut self) -> usize {
+        self.next_id += 1;
+        self.opened.push(self.next_id - 1);
+        self.next_id - 1
+    }
+    fn is_vars(&self, t: &Type) -> bool {
+        self.opened.iter().any(|x| *t == vars(*x))
+    }
+    /// The variable whose `V` is `t`, as in the box of a `!`.
+    fn relation_of(&self, t: &Type) -> Option<usize> {
+        self.opened.iter().copied().find(|x| *t == relation(*x))
+    }
+    fn mentions_opened(&self, t: &Type) -> bool {
+        self.opened.iter().any(|x| t.mentions(*x))
+    }
+    /// `t` with the propositional variables of the bodies of the `∃`s named back.
+    fn original(&self, t: &Type) -> Type {
+        let mut t = t.clone();
+        while let Some(id) = t
+            .ids()
+            .into_iter()
+            .find(|x| t.mentions(*x) && self.renamed.contains_key(x))
+        {
+            t = t.substitute(id, &Type::Var(self.renamed[&id], false));
+        }
+        t
+    }
+    /// Whether `a` and `b` are the same formula, up to the renaming of the bodies of `∃`s.
+    fn same(&self, a: &Type, b: &Type) -> bool {
+        a == b || self.original(a) == self.original(b)
+    }
+    /// Whether `a` and `b` are an opened variable and its dual, or a variable of an `∃` and
+    /// the dual of the formula it stands for. A variable of an `∃` that doesn't stand for a
+    /// formula yet can be paired with any formula that doesn't mention the variables opened
+    /// after it, which it then stands for. Returns the variable, whether `a` is the variable
+    /// and not its dual, and the formula it now stands for, if any.
+    fn pair(&self, a: &Type, b: &Type) -> Option<(usize, bool, Option<Type>)> {
+        if let Type::Eigenvar(x, dual) = *a
+            && *b == !a.clone()
+        {
+            return Some((x, !dual, None));
+        }
+        for (t, other, first) in [(a, b, true), (b, a, false)] {
+            let Type::Eigenvar(x, dual) = *t else {
+                continue;
+            };
+            let Some(witness) = self.witnesses.get(&x) else {
+                continue;
+            };
+            let formula = if dual { other.clone() } else { !other.clone() };
+            let mentioned: Vec<usize> = formula
+                .ids()
+                .into_iter()
+                .filter(|y| formula.mentions(*y))
+                .collect();
+            match witness {
+                Some(witness) if self.same(witness, &formula) => {
+                    return Some((x, first != dual, None));
+                }
+                // A formula of the body of the `∃`, or of an `∃` in it, can't be its witness.
+                // Neither can one without propositional variables, which might be.
+                None if !self.mentions_opened(&formula)
+                    && !mentioned.is_empty()
+                    && mentioned
+                        .iter()
+                        .all(|y| !self.renamed.contains_key(y) || *y < x) =>
+                {
+                    return Some((x, first != dual, Some(formula)));
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+    /// A proof of `sequent` with at most `contractions` contractions in each branch. The
+    /// formulas that the variables of `∃`s stand for are kept only if there is one.
+    fn search(&mut self, sequent: &[Type], contractions: usize) -> Option<Step> {
+        let witnesses = self.witnesses.clone();
+        let step = self.search_step(sequent, contractions);
+        if step.is_none() {
+            self.witnesses = witnesses;
+        }
+        step
+    }
+    fn search_step(&mut self, sequent: &[Type], contractions: usize) -> Option<Step> {
+        let (vars, formulas): (Vec<usize>, Vec<usize>) =
+            (0..sequent.len()).partition(|i| self.is_vars(&sequent[*i]));
+        // Leaves, where the formulas of the opened variables are weakened.
+        let (linear, pair): (Vec<usize>, Vec<usize>) = formulas
+            .iter()
+            .partition(|i| self.relation_of(&sequent[**i]).is_some());
+        if let [a, b] = pair[..] {
+            if let Some((x, a_is_var, witness)) = self.pair(&sequent[a], &sequent[b]) {
+                let (var, dual) = if a_is_var { (a, b) } else { (b, a) };
+                let found = match linear[..] {
+                    [l] if self.relation_of(&sequent[l]) == Some(x) => Some((l, true)),
+                    [] => vars
+                        .iter()
+                        .find(|v| sequent[**v] == self::vars(x))
+                        .map(|v| (*v, false)),
+                    _ => None,
+                };
+                if let Some((v, linear)) = found {
+                    if witness.is_some() {
+                        self.witnesses.insert(x, witness);
+                    }
+                    let rest: Vec<_> = vars.iter().copied().filter(|x| *x != v).collect();
+                    let count = |i: usize| rest.iter().filter(|x| **x < i).count();
+                    return Some(weaken(
+                        &rest,
+                        Step::Variable {
+                            var: var - count(var),
+                            dual: dual - count(dual),
+                            vars: v - count(v),
+                            linear,
+                        },
+                    ));
+                }
+            } else if linear.is_empty()
+                && self.same(&sequent[a], &!sequent[b].clone())
+                && !self.mentions_opened(&sequent[a])
+            {
+                return Some(weaken(&vars, Step::Axiom));
+            }
+        }
+        if let [a] = formulas[..]
+            && sequent[a] == Type::One
+        {
+            return Some(weaken(&vars, Step::One));
+        }
+        // Invertible rules, which can be applied as soon as possible.
+        if let Some(i) = sequent.iter().position(|x| *x == Type::True) {
+            return Some(Step::True(i));
+        }
+        for (i, t) in sequent.iter().enumerate() {
+            let rest = || without(sequent, i);
+            let search = |this: &mut Self, sequent: Vec<Type>| {
+                this.search(&sequent, contractions).map(Box::new)
+            };
+            match t {
+                Type::Par(a, b) => {
+                    let premise = with(rest(), [*a.clone(), *b.clone()]);
+                    return Some(Step::Par(i, search(self, premise)?));
+                }
+                Type::False if sequent.len() > 1 => {
+                    return Some(Step::False(i, search(self, rest())?));
+                }
+                Type::False => return None,
+                Type::With(a, b) => {
+                    let left = search(self, with(rest(), [*a.clone()]))?;
+                    let right = search(self, with(rest(), [*b.clone()]))?;
+                    return Some(Step::With(i, left, right));
+                }
+                Type::All(id, a) => {
+                    let x = self.open();
+                    let body = a.substitute(*id, &Type::Eigenvar(x, false));
+                    let premise = with(rest(), [self::vars(x), body]);
+                    return Some(Step::All(i, search(self, premise)?));
+                }
+                _ => (),
+            }
+        }
+        // The other rules, whose premises might have no proofs when the conclusion does.
+        for &i in &formulas {
+            let rest = without(sequent, i);
+            let step = match &sequent[i] {
+                Type::Times(a, b) => self.search_times(sequent, i, a, b, contractions),
+                Type::Plus(a, b) => None
+                    .or_else(|| {
+                        let premise = with(rest.clone(), [*a.clone()]);
+                        Some(Step::Left(
+                            i,
+                            Box::new(self.search(&premise, contractions)?),
+                        ))
+                    })
+                    .or_else(|| {
+                        let premise = with(rest.clone(), [*b.clone()]);
+                        Some(Step::Right(
+                            i,
+                            Box::new(self.search(&premise, contractions)?),
+                        ))
+                    }),
+                Type::Any(id, a) => self.search_any(&rest, i, *id, a, contractions),
+                Type::Ofc(a) => {
+                    let others: Vec<_> = formulas.iter().copied().filter(|x| *x != i).collect();
+                    // The formula of a variable that `a` mentions goes in the box.
+                    let needed: Vec<_> = vars
+                        .iter()
+                        .copied()
+                        .filter(|v| {
+                            self.opened
+                                .iter()
+                                .any(|x| sequent[*v] == self::vars(*x) && a.mentions(*x))
+                        })
+                        .collect();
+                    // The context of a box has to be a single `?` formula.
+                    match (&others[..], &needed[..]) {
+                        ([], []) => {
+                            let premise = self.search(&[*a.clone()], contractions);
+                            premise.map(|x| weaken(&vars, Step::Exp0(Box::new(x))))
+                        }
+                        ([], [v]) => {
+                            let Type::Why(b) = &sequent[*v] else {
+                                unreachable!()
+                            };
+                            let premise = [*a.clone(), *b.clone()];
+                            let rest: Vec<_> = vars.iter().copied().filter(|x| x != v).collect();
+                            let count = |i: usize| rest.iter().filter(|x| **x < i).count();
+                            self.search(&premise, contractions)
+                                .map(|x| weaken(&rest, Step::Exp1(i - count(i), Box::new(x))))
+                        }
+                        ([j], []) if let Type::Why(b) = &sequent[*j] => {
+                            let premise = [*a.clone(), *b.clone()];
+                            let count = |i: usize| vars.iter().filter(|x| **x < i).count();
+                            self.search(&premise, contractions)
+                                .map(|x| weaken(&vars, Step::Exp1(i - count(i), Box::new(x))))
+                        }
+                        _ => None,
+                    }
+                }
+                Type::Why(a) => None
+                    .or_else(|| {
+                        let premise = with(rest.clone(), [*a.clone()]);
+                        Some(Step::Dere(
+                            i,
+                            Box::new(self.search(&premise, contractions)?),
+                        ))
+                    })
+                    .or_else(|| {
+                        if rest.is_empty() {
+                            return None;
+                        }
+                        Some(Step::Weak(i, Box::new(self.search(&rest, contractions)?)))
+                    })
+                    .or_else(|| {
+                        let premise = with(sequent.to_vec(), [sequent[i].clone()]);
+                        let premise = self.search(&premise, contractions.checked_sub(1)?)?;
+                        Some(Step::Cntr(i, Box::new(premise)))
+                    }),
+                _ => None,
+            };
+            if step.is_some() {
+                return step;
+            }
+        }
+        None
+    }
+    /// Tries every way to split the other formulas between the premises of `A ⊗ B`. The
+    /// formulas of the opened variables go where the variables appear, and are contracted
+    /// when both premises need them.
+    fn search_times(
+        &mut self,
+        sequent: &[Type],
+        i: usize,
+        a: &Type,
+        b: &Type,
+        contractions: usize,
+    ) -> Option<Step> {
+        let (vars, others): (Vec<usize>, Vec<usize>) = (0..sequent.len())
+            .filter(|x| *x != i)
+            .partition(|x| self.is_vars(&sequent[*x]));
+        let witnesses = self.witnesses.clone();
+        for mask in 0..1usize << others.len() {
+            // A left premise found before a right one that failed may have made variables
+            // stand for formulas.
+            self.witnesses = witnesses.clone();
+            let goes_left = |j: usize| others.iter().position(|x| *x == j).unwrap();
+            let mut left: Vec<usize> = others
+                .iter()
+                .copied()
+                .filter(|j| mask & (1 << goes_left(*j)) != 0)
+                .collect();
+            let mut right: Vec<usize> = others
+                .iter()
+                .copied()
+                .filter(|j| !left.contains(j))
+                .collect();
+            let mut premises = sequent.to_vec();
+            let mut copies = vec![];
+            for &v in &vars {
+                let Type::Why(body) = &sequent[v] else {
+                    unreachable!()
+                };
+                let Type::Plus(inner, _) = &**body else {
+                    unreachable!()
+                };
+                let Type::Times(x, _) = &**inner else {
+                    unreachable!()
+                };
+                let Type::Eigenvar(x, _) = **x else {
+                    unreachable!()
+                };
+                let needs = |side: &[usize], t: &Type| {
+                    t.mentions(x) || side.iter().any(|j| sequent[*j].mentions(x))
+                };
+                match (needs(&left, a), needs(&right, b)) {
+                    (true, true) => {
+                        left.push(v);
+                        right.push(premises.len());
+                        premises.push(sequent[v].clone());
+                        copies.push(v);
+                    }
+                    (false, true) => right.push(v),
+                    _ => left.push(v),
+                }
+            }
+            left.sort();
+            right.sort();
+            let premise = |side: &[usize], t: &Type| {
+                with(
+                    side.iter().map(|j| premises[*j].clone()).collect(),
+                    [t.clone()],
+                )
+            };
+            let Some(l) = self.search(&premise(&left, a), contractions) else {
+                continue;
+            };
+            let Some(r) = self.search(&premise(&right, b), contractions) else {
+                continue;
+            };
+            let step = Step::Times(i, left, Box::new(l), Box::new(r));
+            return Some(
+                copies
+                    .into_iter()
+                    .rev()
+                    .fold(step, |step, v| Step::Cntr(v, Box::new(step))),
+            );
+        }
+        None
+    }
+    /// Opens `∃x. A` with a new variable, which the leaves can pair with a formula of the
+    /// sequent that it then stands for. The variable still goes through the box of the `∃`,
+    /// since the type of an `∃` comes from how its box uses the variable.
+    fn search_any(
+        &mut self,
+        rest: &[Type],
+        i: usize,
+        id: usize,
+        a: &Type,
+        contractions: usize,
+    ) -> Option<Step> {
+        let x = self.open();
+        self.witnesses.insert(x, None);
+        let mut body = a.substitute(id, &Type::Eigenvar(x, false));
+        for y in a.ids() {
+            if a.mentions(y) && y != id && !self.opened.contains(&y) {
+                self.next_id += 1;
+                self.renamed.insert(self.next_id - 1, y);
+                body = body.substitute(y, &Type::Var(self.next_id - 1, false));
+            }
+        }
+        let premise = with(rest.to_vec(), [vars(x), body]);
+        let step = self.search(&premise, contractions)?;
+        Some(Step::Any(i, Box::new(step)))
+    }
+}
+
+/// A net under construction, with the names of its free ports in the instructions.
+struct Piece {
+    net: Net,
+    ports: Vec<String>,
+}
+
+impl Piece {
+    /// Puts the port at `order[k]` in position `k`.
+    fn permute(mut self, order: Vec<usize>) -> Piece {
+        self.ports = order.iter().map(|k| self.ports[*k].clone()).collect();
+        reorder(&mut self.net.ports, order.into(), false);
+        self
+    }
+    /// Moves the principal port of a graft, which is the first one, to position `i`.
+    fn place(self, i: usize) -> Piece {
+        let order = (0..self.ports.len())
+            .map(|j| match j {
+                _ if j < i => j + 1,
+                _ if j == i => 0,
+                _ => j,
+            })
+            .collect();
+        self.permute(order)
+    }
+}
+
+/// Builds the net of a derivation, and writes down the instructions that build it.
+#[derive(Default)]
+struct Builder {
+    names: usize,
+    instructions: Vec<String>,
+}
+
+impl Builder {
+    fn name(&mut self) -> String {
+        let letter = (b'a' + (self.names % 26) as u8) as char;
+        let name = match self.names / 26 {
+            0 => letter.to_string(),
+            n => format!("{}{}", letter, n),
+        };
+        self.names += 1;
+        name
+    }
+    fn wire(&mut self) -> Piece {
+        let (a, b) = (self.name(), self.name());
+        self.instructions.push(format!("{} = {}", a, b));
+        Piece {
+            net: Net::named_wire(&a),
+            ports: vec![a, b],
+        }
+    }
+    /// Grafts `agent` onto the ports at the given indices of each piece. Its principal port
+    /// comes first, followed by the remaining ports of each partition.
+    fn graft(&mut self, agent: SymbolId, args: Vec<(Piece, Vec<usize>)>) -> Piece {
+        let principal = self.name();
+        let mut instruction = format!("{:?}", agent);
+        let mut ports = vec![principal.clone()];
+        let mut graft_args = vec![];
+        for (arg, (piece, indices)) in agent.args().into_iter().zip(args) {
+            let names = join_with(
+                indices.iter().map(|x| piece.ports[*x].clone()),
+                " ".to_string(),
+            );
+            match arg {
+                Arg::Partition(_) => {
+                    instruction += &format!("({})", names);
+                    ports.extend(
+                        (0..piece.ports.len())
+                            .filter(|x| !indices.contains(x))
+                            .map(|x| piece.ports[x].clone()),
+                    );
+                    graft_args.push(GraftArg::Partition(piece.net, indices));
+                }
+                Arg::Box(_) => {
+                    instruction += &format!("[{}]", names);
+                    graft_args.push(GraftArg::Box(piece.net, indices));
+                }
+            }
+        }
+        self.instructions
+            .push(format!("{} = {}", instruction, principal));
+        Piece {
+            net: Net::graft(agent, graft_args),
+            ports,
+        }
+    }
+    /// A net with `n` ports for a context `Γ`, followed by one of type `(⅋Γ)'`.
+    fn context(&mut self, n: usize) -> Piece {
+        if n == 0 {
+            return self.graft(SymbolId::One, vec![]);
+        }
+        let mut piece = self.wire();
+        for _ in 1..n {
+            let wire = self.wire();
+            let last = piece.ports.len() - 1;
+            piece = self.graft(SymbolId::Times, vec![(wire, vec![1]), (piece, vec![last])]);
+            let order = (1..piece.ports.len()).chain([0]).collect();
+            piece = piece.permute(order);
+        }
+        piece
+    }
+    /// Joins the first `n` ports of `piece` into one of type `⅋Γ`, or adds a `⊥` port in
+    /// front if there are none.
+    fn pack(&mut self, mut piece: Piece, n: usize) -> Piece {
+        if n == 0 {
+            return self.wrap(SymbolId::False, piece);
+        }
+        for j in (0..n - 1).rev() {
+            piece = self
+                .graft(SymbolId::Par, vec![(piece, vec![j, j + 1])])
+                .place(j);
+        }
+        piece
+    }
+    /// Puts `piece` in the box of a `False` or `Weak`, whose principal port comes first.
+    fn wrap(&mut self, agent: SymbolId, piece: Piece) -> Piece {
+        let n = piece.ports.len();
+        let boxed = self.pack(piece, n);
+        let context = self.context(n);
+        self.graft(agent, vec![(context, vec![n]), (boxed, vec![0])])
+    }
+    /// Builds a proof of a sequent with `n` formulas, whose last rule is `step`.
+    fn build(&mut self, step: Step, n: usize) -> Piece {
+        match step {
+            Step::Axiom => self.wire(),
+            Step::Variable {
+                var,
+                dual,
+                vars,
+                linear: true,
+            } => {
+                let (a, c) = (self.wire(), self.wire());
+                let ac = self.graft(SymbolId::Times, vec![(a, vec![1]), (c, vec![0])]);
+                let piece = self.graft(SymbolId::Left, vec![(ac, vec![0])]);
+                // The ports are the variables formula, the variable and the dual.
+                let mut order = vec![0; 3];
+                (order[vars], order[var], order[dual]) = (0, 1, 2);
+                piece.permute(order)
+            }
+            Step::Variable {
+                var,
+                dual,
+                vars,
+                linear: false,
+            } => {
+                let (a, b, c) = (self.wire(), self.wire(), self.wire());
+                let ac = self.graft(SymbolId::Times, vec![(a, vec![1]), (c, vec![0])]);
+                let bc = self.graft(SymbolId::Times, vec![(b, vec![1]), (ac, vec![2])]);
+                let left = self.graft(SymbolId::Left, vec![(bc, vec![2])]);
+                let left = self.graft(SymbolId::Dere, vec![(left, vec![0])]);
+                let right = self.graft(SymbolId::Right, vec![(left, vec![1])]);
+                let right = self.graft(SymbolId::Dere, vec![(right, vec![0])]);
+                let piece = self.graft(SymbolId::Cntr, vec![(right, vec![1, 0])]);
+                // The ports are the variables formula, the dual and the variable.
+                let mut order = vec![0; 3];
+                (order[vars], order[dual], order[var]) = (0, 1, 2);
+                piece.permute(order)
+            }
+            Step::One => self.graft(SymbolId::One, vec![]),
+            Step::True(i) => {
+                let context = self.context(n - 1);
+                self.graft(SymbolId::True, vec![(context, vec![n - 1])])
+                    .place(i)
+            }
+            Step::False(i, premise) => {
+                let premise = self.build(*premise, n - 1);
+                self.wrap(SymbolId::False, premise).place(i)
+            }
+            Step::Weak(i, premise) => {
+                let premise = self.build(*premise, n - 1);
+                self.wrap(SymbolId::Weak, premise).place(i)
+            }
+            Step::Par(i, premise) => {
+                let premise = self.build(*premise, n + 1);
+                self.graft(SymbolId::Par, vec![(premise, vec![n - 1, n])])
+                    .place(i)
+            }
+            Step::Times(i, left, l, r) => {
+                let l = self.build(*l, left.len() + 1);
+                let r = self.build(*r, n - left.len());
+                let (l_len, r_len) = (l.ports.len(), r.ports.len());
+                let piece = self.graft(
+                    SymbolId::Times,
+                    vec![(l, vec![l_len - 1]), (r, vec![r_len - 1])],
+                );
+                let right = (0..n).filter(|j| *j != i && !left.contains(j));
+                let built: Vec<usize> = [i].into_iter().chain(left.clone()).chain(right).collect();
+                let order = (0..n)
+                    .map(|j| built.iter().position(|x| *x == j).unwrap())
+                    .collect();
+                piece.permute(order)
+            }
+            Step::With(i, l, r) => {
+                let l = self.build(*l, n);
+                let l = self.pack(l, n - 1);
+                let r = self.build(*r, n);
+                let r = self.pack(r, n - 1);
+                let context = self.context(n - 1);
+                self.graft(
+                    SymbolId::With,
+                    vec![(context, vec![n - 1]), (l, vec![1, 0]), (r, vec![1, 0])],
+                )
+                .place(i)
+            }
+            Step::Left(i, premise) => self.unary(SymbolId::Left, i, *premise, n),
+            Step::Right(i, premise) => self.unary(SymbolId::Right, i, *premise, n),
+            Step::Dere(i, premise) => self.unary(SymbolId::Dere, i, *premise, n),
+            Step::All(i, premise) => self.quantifier(SymbolId::All, i, *premise, n),
+            Step::Any(i, premise) => self.quantifier(SymbolId::Any, i, *premise, n),
+            Step::Exp0(premise) => {
+                let premise = self.build(*premise, 1);
+                self.graft(SymbolId::Exp0, vec![(premise, vec![0])])
+            }
+            Step::Exp1(i, premise) => {
+                let premise = self.build(*premise, 2);
+                let wire = self.wire();
+                self.graft(SymbolId::Exp1, vec![(wire, vec![1]), (premise, vec![0, 1])])
+                    .place(i)
+            }
+            Step::Cntr(i, premise) => {
+                let premise = self.build(*premise, n + 1);
+                self.graft(SymbolId::Cntr, vec![(premise, vec![i, n])])
+                    .place(i)
+            }
+        }
+    }
+    /// An agent with a single partition, grafted onto the last port of a premise.
+    fn unary(&mut self, agent: SymbolId, i: usize, premise: Step, n: usize) -> Piece {
+        let premise = self.build(premise, n);
+        self.graft(agent, vec![(premise, vec![n - 1])]).place(i)
+    }
+    /// A quantifier whose premise has the context, the variables formula and the body. The
+    /// context comes from outside the box.
+    fn quantifier(&mut self, agent: SymbolId, i: usize, premise: Step, n: usize) -> Piece {
+        let premise = self.build(premise, n + 1);
+        let boxed = self.pack(premise, n - 1);
+        let context = self.context(n - 1);
+        self.graft(agent, vec![(context, vec![n - 1]), (boxed, vec![0, 1, 2])])
+            .place(i)
+    }
+}
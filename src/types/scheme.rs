@@ -0,0 +1,154 @@
+//! Principal signatures of definitions, inferred one simplicity operation at a time.
+use super::alias::Alias;
+use super::show::Notation;
+use super::{State, Type, TypeError, TypeErrorKind};
+use crate::net::{SymbolId, VarId};
+use std::collections::BTreeMap;
+
+/// The types of the ports of a definition, closed over their propositional variables.
+/// Variables are numbered from 0 in order of appearance, so the same definition always
+/// gets the same scheme.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scheme {
+    /// The generalized variables.
+    pub vars: Vec<usize>,
+    pub ports: Vec<Type>,
+    /// Names of the variables, taken from the wires they come from.
+    pub names: BTreeMap<usize, String>,
+}
+
+impl Scheme {
+    /// Shows the scheme as in `∀a b. |- a', b ⊗ a`, or `@a b |- ~a, b * a` in ASCII.
+    pub fn show_in(&self, notation: Notation) -> String {
+        self.show_with(notation, &[])
+    }
+    /// Like `show_in`, with the formulas that `aliases` stand for written with their names.
+    pub fn show_with(&self, notation: Notation, aliases: &[Alias]) -> String {
+        let mut scope = Type::named_scope(&self.ports, &self.names);
+        let ports = crate::util::join_with(
+            self.ports
+                .iter()
+                .map(|x| x.show_with(&mut scope, notation, aliases)),
+            ", ".to_string(),
+        );
+        if self.vars.is_empty() {
+            return format!("|- {}", ports);
+        }
+        let vars = crate::util::join_with(
+            self.vars.iter().map(|x| scope.get(x).unwrap().clone()),
+            " ".to_string(),
+        );
+        match notation {
+            Notation::Unicode => format!("∀{}. |- {}", vars, ports),
+            Notation::Ascii => format!("@{} |- {}", vars, ports),
+        }
+    }
+}
+
+static NO_NAMES: BTreeMap<VarId, String> = BTreeMap::new();
+
+/// Types a net while it's built by the simplicity operations. Each free port of the net
+/// has a type, and each operation combines the types of the ports it uses.
+#[derive(Debug, Clone)]
+pub struct Typer {
+    state: State<'static>,
+}
+
+impl Default for Typer {
+    fn default() -> Self {
+        Typer {
+            state: State::new(&NO_NAMES),
+        }
+    }
+}
+
+impl Typer {
+    /// The types of the two ends of a new wire, which are dual to each other.
+    pub fn wire(&mut self, name: Option<&str>) -> [Type; 2] {
+        let id = self.state.make_new_var();
+        if let Some(name) = name {
+            self.state.names.insert(id, name.to_string());
+        }
+        [Type::Var(id, false), Type::Var(id, true)]
+    }
+    /// The type of the principal port of `agent` grafted onto ports of types `wires`,
+    /// counting every partition from left to right, and boxing nets with ports of types `boxes`.
+    pub fn graft(
+        &mut self,
+        agent: &SymbolId,
+        wires: Vec<Type>,
+        boxes: Vec<Vec<Type>>,
+    ) -> Result<Type, TypeError> {
+        self.state.rule(agent, wires, boxes)
+    }
+    /// Cuts two ports, which must have dual types.
+    pub fn cut(&mut self, a: Type, b: Type) -> Result<(), TypeError> {
+        self.state.cut(a, b)
+    }
+    /// The types of the ports of a definition with signature `scheme`, with new variables.
+    pub fn instantiate(&mut self, scheme: &Scheme) -> Vec<Type> {
+        let mut ports = scheme.ports.clone();
+        self.state
+            .freshen_vars(&mut ports.iter_mut().collect::<Vec<_>>(), &scheme.names);
+        ports
+    }
+    /// The signature of a net whose ports have types `ports`.
+    pub fn generalize(&self, ports: Vec<Type>) -> Scheme {
+        let mut ports: Vec<Type> = ports.into_iter().map(|x| self.state.resolve(x)).collect();
+        let mut map = BTreeMap::new();
+        let mut vars = vec![];
+        for t in &ports {
+            visit(t, &mut |id, free| {
+                if !map.contains_key(&id) {
+                    map.insert(id, map.len());
+                    if free {
+                        vars.push(map.len() - 1);
+                    }
+                }
+            });
+        }
+        for t in &mut ports {
+            t.replace_vars(&|old| *map.get(&old).unwrap());
+        }
+        let names = map
+            .iter()
+            .filter_map(|(old, new)| Some((*new, self.state.names.get(old)?.clone())))
+            .collect();
+        Scheme { vars, ports, names }
+    }
+    /// `t` with the variables that have been unified replaced.
+    pub fn resolve(&self, t: Type) -> Type {
+        self.state.resolve(t)
+    }
+    /// Names of the variables, taken from the wires they come from.
+    pub fn names(&self) -> &BTreeMap<usize, String> {
+        &self.state.names
+    }
+    /// The error of using a definition that isn't well-typed.
+    pub fn ill_typed_dependency(name: &str) -> TypeError {
+        TypeError {
+            kind: TypeErrorKind::IllTypedDependency(name.to_string()),
+            agent: None,
+            path: vec![],
+            names: BTreeMap::new(),
+        }
+    }
+}
+
+/// Calls `f` with the id of each variable in order of appearance, and whether it's free.
+fn visit(t: &Type, f: &mut impl FnMut(usize, bool)) {
+    match t {
+        Type::Var(id, _) => f(*id, true),
+        Type::Eigenvar(id, _) => f(*id, false),
+        Type::Times(a, b) | Type::Par(a, b) | Type::Plus(a, b) | Type::With(a, b) => {
+            visit(a, f);
+            visit(b, f);
+        }
+        Type::Ofc(a) | Type::Why(a) => visit(a, f),
+        Type::All(id, a) | Type::Any(id, a) => {
+            f(*id, false);
+            visit(a, f);
+        }
+        Type::One | Type::False | Type::Zero | Type::True | Type::Hole => (),
+    }
+}
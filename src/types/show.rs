@@ -1,38 +1,171 @@
+use crate::util::pick_given_name;
 use crate::util::pick_name;
+use crate::util::pick_next_name;
 
+use crate::types::alias::Alias;
 use crate::types::Type;
 
 use crate::net::VarId;
 
 use crate::types::BTreeMap;
 
+/// How `Type::show_in` writes connectives.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Notation {
+    /// `A ⊗ B`, `∀x.A`, `A'`, as `Type::parse` reads them.
+    #[default]
+    Unicode,
+    /// `A * B`, `@x A`, `~A`, as in `future-syntax.md`.
+    Ascii,
+}
+
+// Binding strength of the binary connectives, as in `Parser::parse_formula`.
+// Prefix operators and atoms bind tighter than all of them.
+const PREFIX: usize = 5;
+
 impl Type {
     pub fn show(&self, scope: &mut BTreeMap<VarId, String>) -> String {
-        match self {
-            Type::Times(a, b) => {
-                format!("({} ⊗ {})", a.show(scope), b.show(scope))
-            }
-            Type::One => format!("1"),
-            Type::Par(a, b) => {
-                format!("({} ⅋ {})", a.show(scope), b.show(scope))
-            }
-            Type::False => format!("⊥"),
-            Type::Plus(a, b) => {
-                format!("({} ⊕ {})", a.show(scope), b.show(scope))
+        self.show_in(scope, Notation::Unicode)
+    }
+    /// Shows the formula with as few parentheses as `Type::parse` needs to read it back.
+    pub fn show_in(&self, scope: &mut BTreeMap<VarId, String>, notation: Notation) -> String {
+        self.show_prec(scope, notation, &[], 0, true)
+    }
+    /// Like `show_in`, but the parts of the formula that an alias stands for are
+    /// written with its name, as in `Bool ⊸ Bool`.
+    pub fn show_with(
+        &self,
+        scope: &mut BTreeMap<VarId, String>,
+        notation: Notation,
+        aliases: &[Alias],
+    ) -> String {
+        self.show_prec(scope, notation, aliases, 0, true)
+    }
+    /// The formula as an alias applied to arguments, or the dual of one, if it's any.
+    fn show_alias(
+        &self,
+        scope: &mut BTreeMap<VarId, String>,
+        notation: Notation,
+        aliases: &[Alias],
+    ) -> Option<String> {
+        let dual = !self.clone();
+        let (alias, args, dual) = aliases.iter().find_map(|alias| match alias.matches(self) {
+            Some(args) => Some((alias, args, false)),
+            None => Some((alias, alias.matches(&dual)?, true)),
+        })?;
+        let mut s = alias.name.clone();
+        if !args.is_empty() {
+            let args: Vec<String> = args
+                .iter()
+                .map(|x| x.show_prec(scope, notation, aliases, 0, true))
+                .collect();
+            s = format!("{}({})", s, args.join(", "));
+        }
+        Some(match (dual, notation) {
+            (false, _) => s,
+            (true, Notation::Unicode) => s + "'",
+            (true, Notation::Ascii) => format!("~{}", s),
+        })
+    }
+    /// `prec` is how strongly the surrounding connective binds, and `last` is whether nothing
+    /// follows the formula, in which case a quantifier's body can extend to the end.
+    fn show_prec(
+        &self,
+        scope: &mut BTreeMap<VarId, String>,
+        notation: Notation,
+        aliases: &[Alias],
+        prec: usize,
+        last: bool,
+    ) -> String {
+        if let Some(s) = self.show_alias(scope, notation, aliases) {
+            return s;
+        }
+        let ascii = notation == Notation::Ascii;
+        let binary = |op: (usize, &str, &str), a: &Type, b: &Type, scope: &mut _| {
+            let (level, unicode, ascii_op) = op;
+            let parens = level < prec;
+            let a = a.show_prec(scope, notation, aliases, level + 1, false);
+            let b = b.show_prec(scope, notation, aliases, level, last || parens);
+            let s = format!("{} {} {}", a, if ascii { ascii_op } else { unicode }, b);
+            if parens {
+                format!("({})", s)
+            } else {
+                s
             }
-            Type::With(a, b) => {
-                format!("({} & {})", a.show(scope), b.show(scope))
+        };
+        match self {
+            Type::Plus(a, b) => binary((1, "⊕", "+"), a, b, scope),
+            Type::With(a, b) => binary((2, "&", "&"), a, b, scope),
+            Type::Par(a, b) => binary((3, "⅋", "|"), a, b, scope),
+            Type::Times(a, b) => binary((4, "⊗", "*"), a, b, scope),
+            Type::One => if ascii { "(*)" } else { "1" }.to_string(),
+            Type::False => if ascii { "(|)" } else { "⊥" }.to_string(),
+            Type::Zero => if ascii { "(+)" } else { "0" }.to_string(),
+            Type::True => if ascii { "(&)" } else { "⊤" }.to_string(),
+            Type::Ofc(t) => format!("!{}", t.show_prec(scope, notation, aliases, PREFIX, last)),
+            Type::Why(t) => format!("?{}", t.show_prec(scope, notation, aliases, PREFIX, last)),
+            Type::All(id, body) | Type::Any(id, body) => {
+                let name = pick_name(scope, *id);
+                let body = body.show_prec(scope, notation, aliases, 0, true);
+                let universal = matches!(self, Type::All(..));
+                let s = match (universal, ascii) {
+                    (true, false) => format!("∀{}.{}", name, body),
+                    (false, false) => format!("∃{}.{}", name, body),
+                    (true, true) => format!("@{} {}", name, body),
+                    (false, true) => format!("${} {}", name, body),
+                };
+                // The body extends as far right as possible.
+                if last {
+                    s
+                } else {
+                    format!("({})", s)
+                }
             }
-            Type::Ofc(t) => format!("!{}", t.show(scope)),
-            Type::Why(t) => format!("?{}", t.show(scope)),
-            Type::All(id, body) => format!("∀{}.{}", pick_name(scope, *id), body.show(scope)),
-            Type::Any(id, body) => format!("∃{}.{}", pick_name(scope, *id), body.show(scope)),
             Type::Var(id, invert) | Type::Eigenvar(id, invert) => {
-                pick_name(scope, *id) + if *invert { "'" } else { "" }
+                let name = pick_name(scope, *id);
+                match (invert, ascii) {
+                    (false, _) => name,
+                    (true, false) => name + "'",
+                    (true, true) => format!("~{}", name),
+                }
+            }
+            Type::Hole => "_".to_string(),
+        }
+    }
+}
+
+impl Type {
+    /// A scope for `show` in which the variables of `types` are called by their `names`,
+    /// where they have one, and the rest are called `a`, `b`, ... in order of appearance.
+    pub fn named_scope(types: &[Type], names: &BTreeMap<usize, String>) -> BTreeMap<VarId, String> {
+        fn visit(t: &Type, f: &mut impl FnMut(usize)) {
+            match t {
+                Type::Var(id, _) | Type::Eigenvar(id, _) => f(*id),
+                Type::Times(a, b) | Type::Par(a, b) | Type::Plus(a, b) | Type::With(a, b) => {
+                    visit(a, f);
+                    visit(b, f);
+                }
+                Type::Ofc(a) | Type::Why(a) => visit(a, f),
+                Type::All(id, a) | Type::Any(id, a) => {
+                    f(*id);
+                    visit(a, f);
+                }
+                Type::One | Type::False | Type::Zero | Type::True | Type::Hole => (),
             }
-            Type::Hole => format!("_"),
-            Type::Error => format!("Error"),
-            x => todo!("Can not show {:?}", x),
         }
+        let mut scope = BTreeMap::new();
+        for t in types {
+            visit(t, &mut |id| {
+                if let Some(name) = names.get(&id) {
+                    pick_given_name(&mut scope, id, name);
+                }
+            });
+        }
+        for t in types {
+            visit(t, &mut |id| {
+                pick_next_name(&mut scope, id);
+            });
+        }
+        scope
     }
 }
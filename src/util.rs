@@ -28,6 +28,38 @@ pub fn pick_name(scope: &mut BTreeMap<VarId, String>, id: VarId) -> String {
     }
 }
 
+/// Like `pick_name`, but takes the first name that isn't taken, so that names are
+/// given in the order they're picked.
+pub fn pick_next_name(scope: &mut BTreeMap<VarId, String>, id: VarId) -> String {
+    if let Some(n) = scope.get(&id) {
+        return n.clone();
+    }
+    let mut number = 0;
+    loop {
+        let result = number_to_string(number);
+        if scope.values().all(|x| *x != result) {
+            scope.insert(id, result.clone());
+            break result;
+        }
+        number += 1;
+    }
+}
+
+/// Like `pick_name`, but uses `name`, or `name` followed by a number if it's taken.
+pub fn pick_given_name(scope: &mut BTreeMap<VarId, String>, id: VarId, name: &str) -> String {
+    if let Some(n) = scope.get(&id) {
+        return n.clone();
+    }
+    let mut result = name.to_string();
+    let mut number = 1;
+    while scope.values().any(|x| *x == result) {
+        result = format!("{}{}", name, number);
+        number += 1;
+    }
+    scope.insert(id, result.clone());
+    result
+}
+
 pub fn join_with(a: impl Iterator<Item = String>, joiner: String) -> String {
     a.fold(String::new(), |acc, s| {
         if acc.is_empty() {
@@ -0,0 +1,112 @@
+// Runs the binary, for what the tests in `src/test.rs` don't see: exit statuses, and where
+// the output goes.
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// The output of `linear-nets` with `args`, run in the `tests` directory, and `stdin` as input.
+fn run(args: &[&str], stdin: impl AsRef<[u8]>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_linear-nets"))
+        .args(args)
+        .current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_ref())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn check_good_file() {
+    let output = run(&["check", "compose-functions.line"], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn check_bad_file() {
+    let output = run(&["check", "error-signatures.line"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("`Weakened` isn't well-typed"));
+    assert!(stderr(&output).contains("error-signatures.line:18:3"));
+}
+
+#[test]
+fn missing_file() {
+    let output = run(&["check", "missing.line"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("Can't read `missing.line`"));
+}
+
+#[test]
+fn highest_status() {
+    // A file that can't be read after one with errors.
+    let output = run(&["check", "error-signatures.line", "missing.line"], "");
+    assert_eq!(output.status.code(), Some(2));
+    let output = run(&["check", "missing.line", "error-signatures.line"], "");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn unknown_command() {
+    let output = run(&["frob"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("Unknown command `frob`"));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn format_check() {
+    let output = run(&["fmt", "--check", "compose-functions.line"], "");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let output = run(&["fmt", "--check"], "Main(x y) {\n   x   =   y\n}\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("isn't formatted"));
+    assert!(output.stdout.is_empty());
+    let output = run(&["fmt", "--check"], "Main(x y) {\n  x = y\n}\n");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+}
+
+#[test]
+fn json_output() {
+    // Boxes, holes, aliases, type errors and a file that doesn't parse. `run` adds the normal
+    // forms and their types, and `emit` the interaction combinators of `Main`.
+    let files = [
+        "with.line",
+        "holes.line",
+        "error-types.line",
+        "error-unmatched-var.line",
+    ];
+    for file in files {
+        let mut commands = vec![vec!["check"], vec!["run", "--all"]];
+        if file == "with.line" {
+            commands.push(vec!["emit", "--target", "icomb"]);
+        }
+        for mut args in commands {
+            let name = format!("{}__{}__json", file, args[0]);
+            args.extend(["--json", file]);
+            let output = run(&args, "");
+            let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+            insta::assert_snapshot!(name, serde_json::to_string_pretty(&document).unwrap());
+        }
+    }
+}
+
+#[test]
+fn json_unreadable() {
+    let output = run(&["check", "--json"], [0xff]);
+    assert_eq!(output.status.code(), Some(2));
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(document["files"][0]["file"], "<stdin>");
+    assert_eq!(document["files"][0]["book"], serde_json::Value::Null);
+}